@@ -0,0 +1,62 @@
+use bittorrust::{
+    bencode_parser::{decode_borrowed, decode_bytes, Value},
+    torrent::Torrent,
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+/// Multi-file metainfo with `files` entries and a `pieces` string like a large real torrent
+fn large_torrent(files: usize) -> Vec<u8> {
+    let piece_length = 256 * 1024;
+    let file_length = 64 * 1024;
+    let piece_count = (files * file_length).div_ceil(piece_length);
+    let files = (0..files)
+        .map(|i| {
+            [
+                ("length", Value::from(file_length as i64)),
+                (
+                    "path",
+                    Value::from(vec![
+                        Value::from(format!("dir{}", i / 1000)),
+                        Value::from(format!("file{i}.bin")),
+                    ]),
+                ),
+            ]
+            .into_iter()
+            .collect::<Value>()
+        })
+        .collect::<Vec<_>>();
+    let info: Value = [
+        ("files", Value::from(files)),
+        ("name", Value::from("large")),
+        ("piece length", Value::from(piece_length as i64)),
+        ("pieces", Value::from(vec![0xab; piece_count * 20])),
+    ]
+    .into_iter()
+    .collect();
+    let metainfo: Value = [
+        ("announce", Value::from("http://tracker.example/announce")),
+        ("info", info),
+    ]
+    .into_iter()
+    .collect();
+    metainfo.encode()
+}
+
+fn decode(c: &mut Criterion) {
+    let bytes = large_torrent(100_000);
+    let mut group = c.benchmark_group("100k-file torrent");
+    group.sample_size(20);
+    group.bench_function("decode_bytes (owned)", |b| {
+        b.iter(|| decode_bytes(black_box(&bytes)).unwrap())
+    });
+    group.bench_function("decode_borrowed", |b| {
+        b.iter(|| decode_borrowed(black_box(&bytes)).unwrap())
+    });
+    group.bench_function("Torrent::from_bytes", |b| {
+        b.iter(|| Torrent::from_bytes(black_box(&bytes)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
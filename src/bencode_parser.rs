@@ -1,69 +1,766 @@
-const INT_PREFIX: &str = "i";
-const LIST_PREFIX: &str = "l";
-const DICT_PREFIX: &str = "d";
-const SUFFIX: &str = "e";
-
-pub fn decode(val: &str) -> (serde_json::Value, &str) {
-    match val.split_at(1) {
-        // integer
-        (INT_PREFIX, rest) => {
-            if let Some((int, rest)) = rest.split_once(SUFFIX) {
-                if let Ok(val) = int.parse::<i64>() {
-                    return (serde_json::Value::Number(val.into()), rest);
+use std::{collections::BTreeMap, fmt, ops::Range};
+
+pub mod borrowed;
+pub mod format;
+pub mod limits;
+pub mod path;
+pub mod stream;
+pub mod strict;
+
+pub use borrowed::{decode_borrowed, decode_borrowed_with_limits};
+pub use format::BinaryEncoding;
+pub use limits::DecodeLimits;
+pub use path::{PathError, Query};
+pub use stream::{Decoded, StreamDecoder};
+pub use strict::{decode_strict, lint, StrictError, Violation, ViolationKind};
+
+const INT_PREFIX: u8 = b'i';
+const LIST_PREFIX: u8 = b'l';
+const DICT_PREFIX: u8 = b'd';
+const SUFFIX: u8 = b'e';
+const LENGTH_SEPARATOR: u8 = b':';
+/// Accepted in front of a byte string length, though never canonical
+const LENGTH_SIGN: u8 = b'+';
+
+/// A decoded bencode value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// `i<integer>e`
+    Int(i64),
+    /// `<length>:<bytes>`, not necessarily valid UTF-8
+    Bytes(Vec<u8>),
+    /// `l<values>e`
+    List(Vec<Value>),
+    /// `d<key><value>...e`, keys are byte strings
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(int) => Some(*int),
+            _ => None,
+        }
+    }
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+    /// Byte string as text, if it is valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes()
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+    }
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+    pub fn as_dict(&self) -> Option<&BTreeMap<Vec<u8>, Value>> {
+        match self {
+            Value::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+    /// Looks up `key` if this value is a dictionary
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_dict().and_then(|dict| dict.get(key.as_bytes()))
+    }
+    /// Canonical bencode form, dictionary keys sorted and integers without padding
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out);
+        out
+    }
+    /// Appends the canonical bencode form to `out`
+    pub fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Int(int) => {
+                out.push(INT_PREFIX);
+                out.extend(int.to_string().as_bytes());
+                out.push(SUFFIX);
+            }
+            Value::Bytes(bytes) => encode_byte_string(bytes, out),
+            Value::List(list) => {
+                out.push(LIST_PREFIX);
+                list.iter().for_each(|value| value.encode_to(out));
+                out.push(SUFFIX);
+            }
+            Value::Dict(dict) => {
+                out.push(DICT_PREFIX);
+                // BTreeMap iterates in key order, which is the raw byte order bencode requires
+                dict.iter().for_each(|(key, value)| {
+                    encode_byte_string(key, out);
+                    value.encode_to(out);
+                });
+                out.push(SUFFIX);
+            }
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(int: i64) -> Self {
+        Value::Int(int)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(bytes: &[u8]) -> Self {
+        Value::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::Bytes(bytes)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(list: Vec<Value>) -> Self {
+        Value::List(list)
+    }
+}
+
+impl From<BTreeMap<Vec<u8>, Value>> for Value {
+    fn from(dict: BTreeMap<Vec<u8>, Value>) -> Self {
+        Value::Dict(dict)
+    }
+}
+
+impl<K: Into<Vec<u8>>, V: Into<Value>> FromIterator<(K, V)> for Value {
+    /// Collects key-value pairs into a dictionary
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Value::Dict(
+            iter.into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        )
+    }
+}
+
+/// Why decoding failed, `offset` is the position in the input where the problem was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Input ended before the value was complete
+    UnexpectedEof {
+        offset: usize,
+        expected: &'static str,
+    },
+    /// Byte that does not fit at this position
+    UnexpectedByte {
+        offset: usize,
+        expected: &'static str,
+        found: u8,
+    },
+    /// Integer that is empty, only a sign or out of range
+    InvalidInteger { offset: usize, found: String },
+    /// Byte string length that does not fit in memory
+    InvalidLength { offset: usize, found: String },
+    /// Byte string declaring more bytes than the input has left
+    TruncatedString {
+        offset: usize,
+        declared: usize,
+        available: usize,
+    },
+    /// Dictionary key that is not a byte string
+    NonStringKey { offset: usize, found: &'static str },
+    /// List or dictionary without its closing `e`
+    Unterminated { offset: usize, kind: &'static str },
+    /// Lists and dictionaries nested deeper than [`DecodeLimits::max_depth`]
+    DepthLimitExceeded { offset: usize, limit: usize },
+    /// Byte string longer than [`DecodeLimits::max_string_length`]
+    StringTooLong {
+        offset: usize,
+        length: usize,
+        limit: usize,
+    },
+    /// More values than [`DecodeLimits::max_elements`]
+    TooManyElements { offset: usize, limit: usize },
+    /// Input larger than [`DecodeLimits::max_input_size`], `offset` is where the limit was crossed
+    InputTooLarge { offset: usize, limit: usize },
+}
+
+impl DecodeError {
+    pub fn offset(&self) -> usize {
+        match self {
+            DecodeError::UnexpectedEof { offset, .. }
+            | DecodeError::UnexpectedByte { offset, .. }
+            | DecodeError::InvalidInteger { offset, .. }
+            | DecodeError::InvalidLength { offset, .. }
+            | DecodeError::TruncatedString { offset, .. }
+            | DecodeError::NonStringKey { offset, .. }
+            | DecodeError::Unterminated { offset, .. }
+            | DecodeError::DepthLimitExceeded { offset, .. }
+            | DecodeError::StringTooLong { offset, .. }
+            | DecodeError::TooManyElements { offset, .. }
+            | DecodeError::InputTooLarge { offset, .. } => *offset,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at byte {}: ", self.offset())?;
+        match self {
+            DecodeError::UnexpectedEof { expected, .. } => {
+                write!(f, "expected {expected}, found end of input")
+            }
+            DecodeError::UnexpectedByte {
+                expected, found, ..
+            } => write!(f, "expected {expected}, found {:?}", char::from(*found)),
+            DecodeError::InvalidInteger { found, .. } => {
+                write!(f, "expected an integer, found {found:?}")
+            }
+            DecodeError::InvalidLength { found, .. } => {
+                write!(f, "byte string length {found} is too large")
+            }
+            DecodeError::TruncatedString {
+                declared,
+                available,
+                ..
+            } => write!(
+                f,
+                "byte string declares {declared} bytes but only {available} are left"
+            ),
+            DecodeError::NonStringKey { found, .. } => {
+                write!(f, "expected a byte string dictionary key, found {found}")
+            }
+            DecodeError::Unterminated { kind, .. } => write!(f, "{kind} is never closed with `e`"),
+            DecodeError::DepthLimitExceeded { limit, .. } => {
+                write!(
+                    f,
+                    "lists and dictionaries are nested more than {limit} deep"
+                )
+            }
+            DecodeError::StringTooLong { length, limit, .. } => {
+                write!(
+                    f,
+                    "byte string of {length} bytes exceeds the limit of {limit}"
+                )
+            }
+            DecodeError::TooManyElements { limit, .. } => {
+                write!(f, "more than {limit} values")
+            }
+            DecodeError::InputTooLarge { limit, .. } => {
+                write!(f, "input exceeds the limit of {limit} bytes")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decodes a single value from the start of `val`, returning it with the remaining input
+pub fn decode_bytes(val: &[u8]) -> Result<(Value, &[u8]), DecodeError> {
+    decode_bytes_with_limits(val, DecodeLimits::default())
+}
+
+/// [`decode_bytes`] for untrusted input, failing once any of `limits` is exceeded
+pub fn decode_bytes_with_limits(
+    val: &[u8],
+    limits: DecodeLimits,
+) -> Result<(Value, &[u8]), DecodeError> {
+    let (value, rest) = decode_borrowed_with_limits(val, limits)?;
+    Ok((value.to_owned_value(), rest))
+}
+
+/// Finds the byte range of `key`'s value in the dictionary at the start of `val`, without
+/// re-encoding it, so the exact original bytes can be used (e.g. for hashing the info dict)
+pub fn dict_value_span(val: &[u8], key: &[u8]) -> Result<Option<Range<usize>>, DecodeError> {
+    let mut decoder = Decoder::new(val);
+    decoder.expect(DICT_PREFIX, "a dictionary")?;
+    loop {
+        match decoder.peek() {
+            None => {
+                return Err(DecodeError::Unterminated {
+                    offset: 0,
+                    kind: "dictionary",
+                })
+            }
+            Some(SUFFIX) => return Ok(None),
+            Some(_) => {
+                let entry_key = decoder.key()?;
+                let start = decoder.pos;
+                decoder.skip()?;
+                if entry_key == key {
+                    return Ok(Some(start..decoder.pos));
                 }
             }
         }
-        // list
-        (LIST_PREFIX, rest) => {
-            let (list, rest) = get_list_of_values(rest);
-            return (serde_json::Value::Array(list), rest);
-        }
-        // dictionary
-        (DICT_PREFIX, rest) => {
-            if let Some(val) = rest.strip_suffix(SUFFIX) {
-                let (list, rest) = get_list_of_values(val);
-                let mut dict = serde_json::Map::new();
-                list.iter().enumerate().for_each(|(i, value)| {
-                    if i % 2 == 0 {
-                        if let serde_json::Value::String(key) = value {
-                            if list.len() > i + 1 {
-                                dict.insert(key.to_string(), list[i + 1].clone());
-                            } else {
-                                panic!("Value not provided");
-                            }
-                        } else {
-                            panic!("Key should be a string");
-                        }
+    }
+}
+
+/// Cursor over the input that keeps track of the offset for error reporting
+struct Decoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+    /// Non-canonical encodings seen so far, only collected in strict mode
+    violations: Option<Vec<Violation>>,
+    limits: DecodeLimits,
+    /// Lists and dictionaries currently open
+    depth: usize,
+    /// Values started so far
+    elements: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(input: &'a [u8]) -> Decoder<'a> {
+        Decoder {
+            input,
+            pos: 0,
+            violations: None,
+            limits: DecodeLimits::default(),
+            depth: 0,
+            elements: 0,
+        }
+    }
+    fn with_limits(input: &'a [u8], limits: DecodeLimits) -> Result<Decoder<'a>, DecodeError> {
+        if input.len() > limits.max_input_size {
+            return Err(DecodeError::InputTooLarge {
+                offset: limits.max_input_size,
+                limit: limits.max_input_size,
+            });
+        }
+        Ok(Decoder {
+            limits,
+            ..Decoder::new(input)
+        })
+    }
+    /// Counts a value about to be decoded against the element limit
+    fn count_element(&mut self) -> Result<(), DecodeError> {
+        self.elements += 1;
+        if self.elements > self.limits.max_elements {
+            return Err(DecodeError::TooManyElements {
+                offset: self.pos,
+                limit: self.limits.max_elements,
+            });
+        }
+        Ok(())
+    }
+    /// Opens a list or dictionary, checking the depth limit before recursing into it
+    fn enter(&mut self) -> Result<(), DecodeError> {
+        if self.depth >= self.limits.max_depth {
+            return Err(DecodeError::DepthLimitExceeded {
+                offset: self.pos,
+                limit: self.limits.max_depth,
+            });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+    fn strict(input: &'a [u8]) -> Decoder<'a> {
+        Decoder {
+            violations: Some(Vec::new()),
+            ..Decoder::new(input)
+        }
+    }
+    fn report(&mut self, offset: usize, kind: ViolationKind) {
+        if let Some(violations) = &mut self.violations {
+            violations.push(Violation { offset, kind });
+        }
+    }
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+    fn rest(&self) -> &'a [u8] {
+        &self.input[self.pos..]
+    }
+    /// Consumes `byte`, or fails with what was found instead
+    fn expect(&mut self, byte: u8, expected: &'static str) -> Result<(), DecodeError> {
+        match self.peek() {
+            Some(found) if found == byte => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(found) => Err(DecodeError::UnexpectedByte {
+                offset: self.pos,
+                expected,
+                found,
+            }),
+            None => Err(DecodeError::UnexpectedEof {
+                offset: self.pos,
+                expected,
+            }),
+        }
+    }
+    /// Consumes a run of bytes matching `pred`
+    fn take_while(&mut self, pred: impl Fn(u8) -> bool) -> &'a [u8] {
+        let start = self.pos;
+        while self.peek().is_some_and(&pred) {
+            self.pos += 1;
+        }
+        &self.input[start..self.pos]
+    }
+    fn value(&mut self) -> Result<borrowed::Value<'a>, DecodeError> {
+        self.count_element()?;
+        match self.peek() {
+            None => Err(DecodeError::UnexpectedEof {
+                offset: self.pos,
+                expected: "a value",
+            }),
+            Some(INT_PREFIX) => self.int().map(borrowed::Value::Int),
+            Some(LIST_PREFIX) => self.list(),
+            Some(DICT_PREFIX) => self.dict(),
+            Some(byte) if byte.is_ascii_digit() || byte == LENGTH_SIGN => {
+                self.byte_string().map(borrowed::Value::Bytes)
+            }
+            Some(found) => Err(DecodeError::UnexpectedByte {
+                offset: self.pos,
+                expected: "a value",
+                found,
+            }),
+        }
+    }
+    /// Moves past the next value without building it
+    fn skip(&mut self) -> Result<(), DecodeError> {
+        let kind = match self.peek() {
+            Some(LIST_PREFIX) => "list",
+            Some(DICT_PREFIX) => "dictionary",
+            // integers and byte strings do not allocate
+            _ => return self.value().map(drop),
+        };
+        self.count_element()?;
+        self.enter()?;
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                None => {
+                    return Err(DecodeError::Unterminated {
+                        offset: start,
+                        kind,
+                    })
+                }
+                Some(SUFFIX) => {
+                    self.pos += 1;
+                    self.depth -= 1;
+                    return Ok(());
+                }
+                Some(_) => {
+                    if kind == "dictionary" {
+                        self.key()?;
                     }
-                });
-                return (serde_json::Value::Object(dict), rest);
+                    self.skip()?;
+                }
+            }
+        }
+    }
+    /// `i<integer>e`
+    fn int(&mut self) -> Result<i64, DecodeError> {
+        let start = self.pos;
+        self.expect(INT_PREFIX, "`i`")?;
+        let digits = self.take_while(|b| b.is_ascii_digit() || b == b'-');
+        self.expect(SUFFIX, "a digit or `e` ending the integer")?;
+        match digits {
+            b"-0" => self.report(start, ViolationKind::NegativeZero),
+            [b'0', _, ..] | [b'-', b'0', ..] => {
+                self.report(start, ViolationKind::IntegerLeadingZero)
+            }
+            _ => {}
+        }
+        std::str::from_utf8(digits)
+            .ok()
+            .and_then(|int| int.parse::<i64>().ok())
+            .ok_or_else(|| DecodeError::InvalidInteger {
+                offset: start,
+                found: String::from_utf8_lossy(digits).into_owned(),
+            })
+    }
+    /// `<length>:<bytes>`
+    fn byte_string(&mut self) -> Result<&'a [u8], DecodeError> {
+        let start = self.pos;
+        if self.peek() == Some(LENGTH_SIGN) {
+            self.report(start, ViolationKind::LengthPlusSign);
+            self.pos += 1;
+        }
+        let digits = self.take_while(|b| b.is_ascii_digit());
+        self.expect(LENGTH_SEPARATOR, "a digit or `:` after the string length")?;
+        if digits.len() > 1 && digits[0] == b'0' {
+            self.report(start, ViolationKind::LengthLeadingZero);
+        }
+        // only digits were taken, so this can only fail on overflow
+        let len = std::str::from_utf8(digits)
+            .ok()
+            .and_then(|len| len.parse::<usize>().ok())
+            .ok_or_else(|| DecodeError::InvalidLength {
+                offset: start,
+                found: String::from_utf8_lossy(digits).into_owned(),
+            })?;
+        if len > self.limits.max_string_length {
+            return Err(DecodeError::StringTooLong {
+                offset: start,
+                length: len,
+                limit: self.limits.max_string_length,
+            });
+        }
+        let available = self.input.len() - self.pos;
+        if available < len {
+            return Err(DecodeError::TruncatedString {
+                offset: start,
+                declared: len,
+                available,
+            });
+        }
+        let bytes = &self.input[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+    /// Dictionary key, which must be a byte string
+    fn key(&mut self) -> Result<&'a [u8], DecodeError> {
+        let found = match self.peek() {
+            Some(byte) if byte.is_ascii_digit() || byte == LENGTH_SIGN => {
+                return self.byte_string()
+            }
+            Some(INT_PREFIX) => "an integer",
+            Some(LIST_PREFIX) => "a list",
+            Some(DICT_PREFIX) => "a dictionary",
+            _ => return self.byte_string(),
+        };
+        Err(DecodeError::NonStringKey {
+            offset: self.pos,
+            found,
+        })
+    }
+    /// `l<values>e`
+    fn list(&mut self) -> Result<borrowed::Value<'a>, DecodeError> {
+        let start = self.pos;
+        self.enter()?;
+        self.expect(LIST_PREFIX, "`l`")?;
+        let mut list = vec![];
+        loop {
+            match self.peek() {
+                None => {
+                    return Err(DecodeError::Unterminated {
+                        offset: start,
+                        kind: "list",
+                    })
+                }
+                Some(SUFFIX) => {
+                    self.pos += 1;
+                    self.depth -= 1;
+                    return Ok(borrowed::Value::List(list));
+                }
+                Some(_) => list.push(self.value()?),
             }
         }
-        (_, _) => {
-            // byte string
-            if let Some((len, rest)) = val.split_once(':') {
-                if let Ok(len) = len.parse::<usize>() {
-                    return (
-                        serde_json::Value::String(rest[..len].to_string()),
-                        &rest[len..],
-                    );
+    }
+    /// `d<key><value>...e`
+    fn dict(&mut self) -> Result<borrowed::Value<'a>, DecodeError> {
+        let start = self.pos;
+        self.enter()?;
+        self.expect(DICT_PREFIX, "`d`")?;
+        let mut dict = vec![];
+        let mut previous_key: Option<&[u8]> = None;
+        loop {
+            match self.peek() {
+                None => {
+                    return Err(DecodeError::Unterminated {
+                        offset: start,
+                        kind: "dictionary",
+                    })
+                }
+                Some(SUFFIX) => {
+                    self.pos += 1;
+                    self.depth -= 1;
+                    return Ok(borrowed::Value::Dict(dict));
+                }
+                Some(_) => {
+                    let key_start = self.pos;
+                    let key = self.key()?;
+                    match previous_key {
+                        Some(previous) if key < previous => {
+                            self.report(key_start, ViolationKind::UnsortedKey)
+                        }
+                        Some(previous) if key == previous => {
+                            self.report(key_start, ViolationKind::DuplicateKey)
+                        }
+                        _ => {}
+                    }
+                    previous_key = Some(key);
+                    if self.peek() == Some(SUFFIX) {
+                        return Err(DecodeError::UnexpectedByte {
+                            offset: self.pos,
+                            expected: "a value for the dictionary key",
+                            found: SUFFIX,
+                        });
+                    }
+                    let value = self.value()?;
+                    dict.push((key, value));
                 }
             }
         }
     }
-    panic!("Unknown argument");
 }
 
-pub fn get_list_of_values(val: &str) -> (Vec<serde_json::Value>, &str) {
-    let mut list = vec![];
-    let mut rest = val;
-    while !rest.is_empty() && !rest.starts_with('e') {
-        let (decoded, r) = decode(rest);
-        list.push(decoded.clone());
-        rest = r;
+/// Encodes a value into canonical bencode
+pub fn encode(value: &Value) -> Vec<u8> {
+    value.encode()
+}
+
+/// Appends `<length>:<bytes>` to `out`
+pub fn encode_byte_string(bytes: &[u8], out: &mut Vec<u8>) {
+    out.extend(bytes.len().to_string().as_bytes());
+    out.push(LENGTH_SEPARATOR);
+    out.extend(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(input: &[u8]) -> Result<Value, DecodeError> {
+        decode_bytes(input).map(|(value, _)| value)
+    }
+
+    fn decode_limited(input: &[u8], limits: DecodeLimits) -> DecodeError {
+        decode_bytes_with_limits(input, limits).unwrap_err()
+    }
+
+    #[test]
+    fn decodes_nested_values() {
+        let value = decode(b"d4:listli-3ei0e3:\xff\x00\x01e3:numi42ee").unwrap();
+        assert_eq!(value.get("num").and_then(Value::as_int), Some(42));
+        assert_eq!(
+            value.get("list").and_then(Value::as_list),
+            Some(
+                [
+                    Value::Int(-3),
+                    Value::Int(0),
+                    Value::Bytes(vec![0xff, 0, 1])
+                ]
+                .as_slice()
+            )
+        );
+    }
+
+    #[test]
+    fn returns_trailing_input() {
+        let (value, rest) = decode_bytes(b"0:i1e").unwrap();
+        assert_eq!(value, Value::Bytes(vec![]));
+        assert_eq!(rest, b"i1e");
     }
-    if rest.starts_with('e') {
-        rest = &rest[1..];
+
+    #[test]
+    fn round_trips_canonical_input() {
+        let input: &[u8] =
+            b"d8:announce3:url4:infod6:lengthi-7e4:name2:\xc3\x28e4:listli0e0:lleeee";
+        let (value, rest) = decode_bytes(input).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(value.encode(), input);
+    }
+
+    #[test]
+    fn encodes_canonically() {
+        let (value, _) = decode_bytes(b"d1:bi007e1:a+2:xye").unwrap();
+        assert_eq!(encode(&value), b"d1:a2:xy1:bi7ee");
+        let value = Value::Dict(BTreeMap::from([
+            (b"z".to_vec(), Value::Int(-1)),
+            (b"B".to_vec(), Value::List(vec![])),
+            (b"a".to_vec(), Value::Bytes(b"\x00".to_vec())),
+        ]));
+        assert_eq!(value.encode(), b"d1:Ble1:a1:\x001:zi-1ee");
+    }
+
+    #[test]
+    fn reports_error_offsets() {
+        let cases: [(&[u8], usize); 8] = [
+            (b"", 0),
+            (b"i12", 3),
+            (b"ixe", 1),
+            (b"li1ei-ee", 4),
+            (b"5:abc", 0),
+            (b"di1ei2ee", 1),
+            (b"d1:al", 4),
+            (b"d1:ae", 4),
+        ];
+        for (input, offset) in cases {
+            let err = decode(input).unwrap_err();
+            assert_eq!(err.offset(), offset, "{err}");
+        }
+        assert_eq!(
+            decode(b"i99999999999999999999e"),
+            Err(DecodeError::InvalidInteger {
+                offset: 0,
+                found: "99999999999999999999".to_string()
+            })
+        );
+        assert_eq!(
+            decode(b"3:ab"),
+            Err(DecodeError::TruncatedString {
+                offset: 0,
+                declared: 3,
+                available: 2
+            })
+        );
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limits = DecodeLimits {
+            max_depth: 2,
+            max_string_length: 3,
+            max_elements: 3,
+            max_input_size: 8,
+        };
+        assert!(decode_bytes_with_limits(
+            b"ll3:abcee",
+            DecodeLimits {
+                max_input_size: 9,
+                ..limits
+            }
+        )
+        .is_ok());
+        assert_eq!(
+            decode_limited(b"llleee", limits),
+            DecodeError::DepthLimitExceeded {
+                offset: 2,
+                limit: 2
+            }
+        );
+        assert_eq!(
+            decode_limited(b"l4:abcde", limits),
+            DecodeError::StringTooLong {
+                offset: 1,
+                length: 4,
+                limit: 3
+            }
+        );
+        assert_eq!(
+            decode_limited(
+                b"li1ei2ee",
+                DecodeLimits {
+                    max_elements: 2,
+                    ..limits
+                }
+            ),
+            DecodeError::TooManyElements {
+                offset: 4,
+                limit: 2
+            }
+        );
+        assert_eq!(
+            decode_limited(b"i12345678e", limits),
+            DecodeError::InputTooLarge {
+                offset: 8,
+                limit: 8
+            }
+        );
     }
-    (list, rest)
 }
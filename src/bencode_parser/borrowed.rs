@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+
+use super::{DecodeError, DecodeLimits, Decoder};
+
+/// A decoded bencode value that borrows its byte strings from the input buffer.
+///
+/// Decoding into this does not copy any string, which matters for metainfo with
+/// multi-megabyte `pieces` and many `files` entries. Convert with
+/// [`to_owned_value`](Value::to_owned_value) when the value has to outlive the buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value<'a> {
+    Int(i64),
+    Bytes(&'a [u8]),
+    List(Vec<Value<'a>>),
+    /// Entries in the order they appear in the input
+    Dict(Vec<(&'a [u8], Value<'a>)>),
+}
+
+impl<'a> Value<'a> {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(int) => Some(*int),
+            _ => None,
+        }
+    }
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+    /// Byte string as text, if it is valid UTF-8
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.as_bytes()
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+    }
+    pub fn as_list(&self) -> Option<&[Value<'a>]> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+    pub fn as_dict(&self) -> Option<&[(&'a [u8], Value<'a>)]> {
+        match self {
+            Value::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+    /// Looks up `key` if this value is a dictionary, the last entry wins for repeated keys
+    pub fn get(&self, key: &str) -> Option<&Value<'a>> {
+        self.as_dict()?
+            .iter()
+            .rev()
+            .find(|(entry_key, _)| *entry_key == key.as_bytes())
+            .map(|(_, value)| value)
+    }
+    /// Copies the value out of the input buffer
+    pub fn to_owned_value(&self) -> super::Value {
+        match self {
+            Value::Int(int) => super::Value::Int(*int),
+            Value::Bytes(bytes) => super::Value::Bytes(bytes.to_vec()),
+            Value::List(list) => {
+                super::Value::List(list.iter().map(Value::to_owned_value).collect())
+            }
+            Value::Dict(dict) => super::Value::Dict(
+                dict.iter()
+                    .map(|(key, value)| (key.to_vec(), value.to_owned_value()))
+                    .collect::<BTreeMap<_, _>>(),
+            ),
+        }
+    }
+}
+
+impl From<Value<'_>> for super::Value {
+    fn from(value: Value<'_>) -> Self {
+        value.to_owned_value()
+    }
+}
+
+/// Decodes a single value from the start of `val` without copying byte strings,
+/// returning it with the remaining input
+pub fn decode_borrowed(val: &[u8]) -> Result<(Value<'_>, &[u8]), DecodeError> {
+    decode_borrowed_with_limits(val, DecodeLimits::default())
+}
+
+/// [`decode_borrowed`] for untrusted input, failing once any of `limits` is exceeded
+pub fn decode_borrowed_with_limits(
+    val: &[u8],
+    limits: DecodeLimits,
+) -> Result<(Value<'_>, &[u8]), DecodeError> {
+    let mut decoder = Decoder::with_limits(val, limits)?;
+    let value = decoder.value()?;
+    Ok((value, decoder.rest()))
+}
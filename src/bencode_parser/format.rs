@@ -0,0 +1,115 @@
+use std::fmt::Write;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use super::Value;
+
+/// Binary byte strings longer than this are cut short in the tree view
+const TREE_BINARY_PREVIEW: usize = 32;
+
+/// How byte strings that are not valid UTF-8 are written as text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BinaryEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+impl BinaryEncoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BinaryEncoding::Hex => "hex",
+            BinaryEncoding::Base64 => "base64",
+        }
+    }
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            BinaryEncoding::Hex => hex::encode(bytes),
+            BinaryEncoding::Base64 => STANDARD.encode(bytes),
+        }
+    }
+}
+
+impl Value {
+    /// JSON representation.
+    ///
+    /// UTF-8 byte strings become JSON strings. Other byte strings become `{"hex": "..."}` or
+    /// `{"base64": "..."}` so they cannot be mistaken for text; as dictionary keys, where only
+    /// strings are allowed, they are written as `"hex:..."` or `"base64:..."`.
+    pub fn to_json(&self, binary: BinaryEncoding) -> serde_json::Value {
+        match self {
+            Value::Int(int) => serde_json::Value::Number((*int).into()),
+            Value::Bytes(bytes) => match std::str::from_utf8(bytes) {
+                Ok(text) => serde_json::Value::String(text.to_string()),
+                Err(_) => serde_json::json!({ binary.name(): binary.encode(bytes) }),
+            },
+            Value::List(list) => {
+                serde_json::Value::Array(list.iter().map(|value| value.to_json(binary)).collect())
+            }
+            Value::Dict(dict) => serde_json::Value::Object(
+                dict.iter()
+                    .map(|(key, value)| {
+                        let key = match std::str::from_utf8(key) {
+                            Ok(key) => key.to_string(),
+                            Err(_) => format!("{}:{}", binary.name(), binary.encode(key)),
+                        };
+                        (key, value.to_json(binary))
+                    })
+                    .collect(),
+            ),
+        }
+    }
+    /// Indented, one node per line view meant for reading, long binary strings are shortened
+    pub fn to_tree(&self, binary: BinaryEncoding) -> String {
+        let mut out = String::new();
+        self.write_tree(&mut out, 0, binary);
+        out
+    }
+    fn write_tree(&self, out: &mut String, depth: usize, binary: BinaryEncoding) {
+        match self {
+            Value::Int(int) => {
+                let _ = writeln!(out, "{int}");
+            }
+            Value::Bytes(bytes) => match std::str::from_utf8(bytes) {
+                Ok(text) => {
+                    let _ = writeln!(out, "{text:?}");
+                }
+                Err(_) => {
+                    let preview = &bytes[..bytes.len().min(TREE_BINARY_PREVIEW)];
+                    let ellipsis = if preview.len() < bytes.len() {
+                        "..."
+                    } else {
+                        ""
+                    };
+                    let _ = writeln!(
+                        out,
+                        "<{} bytes> {}:{}{ellipsis}",
+                        bytes.len(),
+                        binary.name(),
+                        binary.encode(preview)
+                    );
+                }
+            },
+            Value::List(list) => {
+                let _ = writeln!(out, "list ({} items)", list.len());
+                for (i, value) in list.iter().enumerate() {
+                    let _ = write!(out, "{:indent$}[{i}] ", "", indent = (depth + 1) * 2);
+                    value.write_tree(out, depth + 1, binary);
+                }
+            }
+            Value::Dict(dict) => {
+                let _ = writeln!(out, "dict ({} keys)", dict.len());
+                for (key, value) in dict {
+                    let _ = write!(
+                        out,
+                        "{:indent$}{}: ",
+                        "",
+                        String::from_utf8_lossy(key),
+                        indent = (depth + 1) * 2
+                    );
+                    value.write_tree(out, depth + 1, binary);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+/// Bounds on what the decoder accepts, so hostile input cannot exhaust the stack or memory.
+///
+/// Exceeding a limit fails with the matching [`DecodeError`](super::DecodeError) variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Deepest nesting of lists and dictionaries
+    pub max_depth: usize,
+    /// Longest byte string, in bytes
+    pub max_string_length: usize,
+    /// Most values in one document, counting every nested value
+    pub max_elements: usize,
+    /// Largest input, in bytes
+    pub max_input_size: usize,
+}
+
+impl Default for DecodeLimits {
+    /// Only nesting is limited, deep enough for any real metainfo but well within the stack
+    fn default() -> Self {
+        DecodeLimits {
+            max_depth: 256,
+            max_string_length: usize::MAX,
+            max_elements: usize::MAX,
+            max_input_size: usize::MAX,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// Limits for input received from trackers and peers
+    pub fn network() -> Self {
+        DecodeLimits {
+            max_depth: 32,
+            max_string_length: 8 * 1024 * 1024,
+            max_elements: 500_000,
+            max_input_size: 16 * 1024 * 1024,
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::{fmt, str::FromStr};
+
+use super::Value;
+
+/// One step of a [`Query`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// Dictionary entry, `info` or `["piece length"]`
+    Key(Vec<u8>),
+    /// List item, `[3]`, negative indexes count from the end
+    Index(i64),
+    /// Every list item or dictionary value, `*` or `[*]`
+    Wildcard,
+}
+
+/// Path expression selecting nodes of a [`Value`], e.g. `info.files[3].path`,
+/// `announce-list[0][*]` or `info["piece length"]`.
+///
+/// Keys are written bare up to the next `.` or `[`, so `info.piece length` works too;
+/// keys containing those characters can be quoted in brackets.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Query {
+    pub segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathError {
+    /// Character offset in the path expression
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid path at character {}: {}",
+            self.offset, self.message
+        )
+    }
+}
+
+impl std::error::Error for PathError {}
+
+impl FromStr for Query {
+    type Err = PathError;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = path.chars().collect();
+        let mut segments = vec![];
+        let mut pos = 0;
+        while pos < chars.len() {
+            match chars[pos] {
+                '[' => {
+                    let (segment, end) = parse_bracket(&chars, pos)?;
+                    segments.push(segment);
+                    pos = end;
+                }
+                '.' if pos == 0 => {
+                    return Err(PathError {
+                        offset: pos,
+                        message: "path cannot start with `.`",
+                    })
+                }
+                '.' => {
+                    pos += 1;
+                    let (segment, end) = parse_bare_key(&chars, pos)?;
+                    segments.push(segment);
+                    pos = end;
+                }
+                _ if pos == 0 => {
+                    let (segment, end) = parse_bare_key(&chars, pos)?;
+                    segments.push(segment);
+                    pos = end;
+                }
+                _ => {
+                    return Err(PathError {
+                        offset: pos,
+                        message: "expected `.` or `[`",
+                    })
+                }
+            }
+        }
+        Ok(Query { segments })
+    }
+}
+
+/// Key up to the next `.` or `[`, `*` meaning every value
+fn parse_bare_key(chars: &[char], start: usize) -> Result<(Segment, usize), PathError> {
+    let end = chars[start..]
+        .iter()
+        .position(|&c| c == '.' || c == '[')
+        .map_or(chars.len(), |len| start + len);
+    if end == start {
+        return Err(PathError {
+            offset: start,
+            message: "expected a key",
+        });
+    }
+    let key: String = chars[start..end].iter().collect();
+    let segment = if key == "*" {
+        Segment::Wildcard
+    } else {
+        Segment::Key(key.into_bytes())
+    };
+    Ok((segment, end))
+}
+
+/// `[<index>]`, `[*]` or `["<key>"]`, starting at the `[`
+fn parse_bracket(chars: &[char], start: usize) -> Result<(Segment, usize), PathError> {
+    let mut pos = start + 1;
+    let segment = if chars.get(pos) == Some(&'"') {
+        pos += 1;
+        let mut key = String::new();
+        loop {
+            match chars.get(pos) {
+                None => {
+                    return Err(PathError {
+                        offset: pos,
+                        message: "unterminated quoted key",
+                    })
+                }
+                Some('"') => break,
+                Some('\\') if chars.get(pos + 1).is_some() => {
+                    key.push(chars[pos + 1]);
+                    pos += 1;
+                }
+                Some(&c) => key.push(c),
+            }
+            pos += 1;
+        }
+        pos += 1;
+        Segment::Key(key.into_bytes())
+    } else {
+        let close = chars[pos..]
+            .iter()
+            .position(|&c| c == ']')
+            .map(|len| pos + len)
+            .ok_or(PathError {
+                offset: start,
+                message: "unterminated `[`",
+            })?;
+        let inner: String = chars[pos..close].iter().collect();
+        pos = close;
+        if inner == "*" {
+            Segment::Wildcard
+        } else {
+            inner
+                .parse::<i64>()
+                .map(Segment::Index)
+                .map_err(|_| PathError {
+                    offset: start + 1,
+                    message: "expected an index, `*` or a quoted key",
+                })?
+        }
+    };
+    if chars.get(pos) != Some(&']') {
+        return Err(PathError {
+            offset: pos,
+            message: "expected `]`",
+        });
+    }
+    Ok((segment, pos + 1))
+}
+
+impl Query {
+    /// Nodes of `root` matched by the query, in document order
+    pub fn select<'v>(&self, root: &'v Value) -> Vec<&'v Value> {
+        let mut selected = vec![root];
+        for segment in &self.segments {
+            selected = selected
+                .into_iter()
+                .flat_map(|value| segment.children(value))
+                .collect();
+        }
+        selected
+    }
+}
+
+impl Segment {
+    fn children<'v>(&self, value: &'v Value) -> Vec<&'v Value> {
+        match (self, value) {
+            (Segment::Key(key), Value::Dict(dict)) => dict.get(key).into_iter().collect(),
+            (Segment::Index(index), Value::List(list)) => {
+                let index = if *index < 0 {
+                    list.len().checked_sub(index.unsigned_abs() as usize)
+                } else {
+                    Some(*index as usize)
+                };
+                index.and_then(|i| list.get(i)).into_iter().collect()
+            }
+            (Segment::Wildcard, Value::List(list)) => list.iter().collect(),
+            (Segment::Wildcard, Value::Dict(dict)) => dict.values().collect(),
+            _ => vec![],
+        }
+    }
+}
+
+impl Value {
+    /// Nodes matched by a path expression, see [`Query`] for the syntax
+    pub fn query(&self, path: &str) -> Result<Vec<&Value>, PathError> {
+        Ok(path.parse::<Query>()?.select(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: &str) -> Segment {
+        Segment::Key(key.as_bytes().to_vec())
+    }
+
+    fn error_offset(path: &str) -> usize {
+        path.parse::<Query>().unwrap_err().offset
+    }
+
+    #[test]
+    fn parses_keys_indexes_and_wildcards() {
+        let query: Query = "info.files[-1].path[*]".parse().unwrap();
+        assert_eq!(
+            query.segments,
+            [
+                key("info"),
+                key("files"),
+                Segment::Index(-1),
+                key("path"),
+                Segment::Wildcard
+            ]
+        );
+        let query: Query = r#"info.piece length["a.b[\"c"].*"#.parse().unwrap();
+        assert_eq!(
+            query.segments,
+            [
+                key("info"),
+                key("piece length"),
+                key("a.b[\"c"),
+                Segment::Wildcard
+            ]
+        );
+        assert_eq!("".parse::<Query>().unwrap(), Query::default());
+    }
+
+    #[test]
+    fn reports_error_offsets() {
+        assert_eq!(error_offset(".info"), 0);
+        assert_eq!(error_offset("info..name"), 5);
+        assert_eq!(error_offset("info."), 5);
+        assert_eq!(error_offset(r#"info["name"#), 10);
+        assert_eq!(error_offset(r#"info["name"x"#), 11);
+        assert_eq!(error_offset("files[1"), 5);
+        assert_eq!(error_offset("files[x]"), 6);
+        assert_eq!(error_offset("files[1]x"), 8);
+    }
+
+    #[test]
+    fn selects_nodes() {
+        let value: Value = [
+            ("a", Value::List(vec![Value::Int(1), Value::Int(2)])),
+            ("b", Value::from("x")),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(value.query("a[-1]").unwrap(), [&Value::Int(2)]);
+        assert_eq!(value.query("a[2]").unwrap(), Vec::<&Value>::new());
+        assert_eq!(value.query("a[-3]").unwrap(), Vec::<&Value>::new());
+        assert_eq!(value.query("*").unwrap().len(), 2);
+        assert_eq!(value.query("a[*]").unwrap().len(), 2);
+        assert_eq!(value.query("b.c").unwrap(), Vec::<&Value>::new());
+        assert_eq!(value.query("").unwrap(), [&value]);
+    }
+}
@@ -0,0 +1,334 @@
+use super::{
+    decode_bytes_with_limits, DecodeError, DecodeLimits, Value, DICT_PREFIX, INT_PREFIX,
+    LENGTH_SEPARATOR, LENGTH_SIGN, LIST_PREFIX, SUFFIX,
+};
+
+/// Result of polling a [`StreamDecoder`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoded {
+    /// A complete value, its bytes are removed from the decoder's buffer
+    Complete(Value),
+    /// The buffered bytes are a prefix of a value, feed more input
+    NeedMore,
+}
+
+/// Where the scanner is inside the value being buffered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanState {
+    /// Expecting the start of a value, or `e` when inside a list/dict
+    Value,
+    /// Inside `i...e`
+    Int,
+    /// Reading the length prefix of a byte string
+    Length(usize),
+    /// Skipping over the contents of a byte string
+    Bytes(usize),
+}
+
+/// Decoder for values that arrive in chunks, e.g. from a socket or an HTTP body.
+///
+/// Bytes are scanned as they are fed so that each poll only looks at new input;
+/// the value is decoded once its last byte has arrived.
+#[derive(Debug, Clone)]
+pub struct StreamDecoder {
+    buf: Vec<u8>,
+    /// Number of bytes of `buf` already scanned
+    scanned: usize,
+    /// Number of open lists/dicts
+    depth: usize,
+    /// Number of values started in the value being scanned
+    elements: usize,
+    state: ScanState,
+    limits: DecodeLimits,
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamDecoder {
+    pub fn new() -> StreamDecoder {
+        StreamDecoder::with_limits(DecodeLimits::default())
+    }
+    /// Decoder that stops buffering as soon as the value being received exceeds `limits`
+    pub fn with_limits(limits: DecodeLimits) -> StreamDecoder {
+        StreamDecoder {
+            buf: Vec::new(),
+            scanned: 0,
+            depth: 0,
+            elements: 0,
+            state: ScanState::Value,
+            limits,
+        }
+    }
+    /// Appends received bytes
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+    /// Bytes fed but not yet returned as part of a value
+    pub fn buffered(&self) -> &[u8] {
+        &self.buf
+    }
+    /// Returns the next value if all of its bytes have been fed
+    pub fn decode(&mut self) -> Result<Decoded, DecodeError> {
+        match self.next_value_bytes()? {
+            Some(bytes) => Ok(Decoded::Complete(
+                decode_bytes_with_limits(&bytes, self.limits)?.0,
+            )),
+            None => Ok(Decoded::NeedMore),
+        }
+    }
+    /// Takes the encoded bytes of the next value once all of them have been fed, so the caller
+    /// can decode them with [`decode_borrowed`](super::decode_borrowed) instead of copying
+    pub fn next_value_bytes(&mut self) -> Result<Option<Vec<u8>>, DecodeError> {
+        let Some(end) = self.scan()? else {
+            return Ok(None);
+        };
+        self.scanned = 0;
+        self.elements = 0;
+        Ok(Some(self.buf.drain(..end).collect()))
+    }
+    /// Advances over the unscanned bytes, returning the end offset of the first value once known
+    fn scan(&mut self) -> Result<Option<usize>, DecodeError> {
+        while self.scanned < self.buf.len() {
+            if self.scanned >= self.limits.max_input_size {
+                return Err(DecodeError::InputTooLarge {
+                    offset: self.scanned,
+                    limit: self.limits.max_input_size,
+                });
+            }
+            let byte = self.buf[self.scanned];
+            let mut value_ended = false;
+            if self.state == ScanState::Value && byte != SUFFIX {
+                self.elements += 1;
+                if self.elements > self.limits.max_elements {
+                    return Err(DecodeError::TooManyElements {
+                        offset: self.scanned,
+                        limit: self.limits.max_elements,
+                    });
+                }
+            }
+            match self.state {
+                ScanState::Value => match byte {
+                    INT_PREFIX => self.state = ScanState::Int,
+                    LIST_PREFIX | DICT_PREFIX if self.depth >= self.limits.max_depth => {
+                        return Err(DecodeError::DepthLimitExceeded {
+                            offset: self.scanned,
+                            limit: self.limits.max_depth,
+                        })
+                    }
+                    LIST_PREFIX | DICT_PREFIX => self.depth += 1,
+                    SUFFIX if self.depth > 0 => {
+                        self.depth -= 1;
+                        value_ended = true;
+                    }
+                    b'0'..=b'9' => self.state = ScanState::Length((byte - b'0') as usize),
+                    LENGTH_SIGN => self.state = ScanState::Length(0),
+                    _ => {
+                        return Err(DecodeError::UnexpectedByte {
+                            offset: self.scanned,
+                            expected: "a value",
+                            found: byte,
+                        })
+                    }
+                },
+                ScanState::Int => {
+                    if byte == SUFFIX {
+                        self.state = ScanState::Value;
+                        value_ended = true;
+                    }
+                }
+                ScanState::Length(len) => match byte {
+                    b'0'..=b'9' => {
+                        let len = len
+                            .checked_mul(10)
+                            .and_then(|len| len.checked_add((byte - b'0') as usize))
+                            .ok_or_else(|| DecodeError::InvalidLength {
+                                offset: self.scanned,
+                                found: format!("{len}{}", char::from(byte)),
+                            })?;
+                        self.state = ScanState::Length(len);
+                    }
+                    LENGTH_SEPARATOR if len > self.limits.max_string_length => {
+                        return Err(DecodeError::StringTooLong {
+                            offset: self.scanned,
+                            length: len,
+                            limit: self.limits.max_string_length,
+                        })
+                    }
+                    LENGTH_SEPARATOR if len == 0 => {
+                        self.state = ScanState::Value;
+                        value_ended = true;
+                    }
+                    LENGTH_SEPARATOR => self.state = ScanState::Bytes(len),
+                    _ => {
+                        return Err(DecodeError::UnexpectedByte {
+                            offset: self.scanned,
+                            expected: "a digit or `:` after the string length",
+                            found: byte,
+                        })
+                    }
+                },
+                ScanState::Bytes(remaining) => {
+                    // skip the string contents in one step instead of byte by byte
+                    let available = self.buf.len() - self.scanned;
+                    if available < remaining {
+                        self.scanned = self.buf.len();
+                        self.state = ScanState::Bytes(remaining - available);
+                        continue;
+                    }
+                    self.scanned += remaining - 1;
+                    self.state = ScanState::Value;
+                    value_ended = true;
+                }
+            }
+            self.scanned += 1;
+            if value_ended && self.depth == 0 {
+                return Ok(Some(self.scanned));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode_parser::decode_bytes;
+
+    const VALUES: &[u8] = b"d3:cow3:moo4:spaml1:ai-12eee0:i7e4:\x00e:d";
+
+    /// Every value of [`VALUES`], decoded in one go
+    fn expected() -> Vec<Value> {
+        let mut values = vec![];
+        let mut rest = VALUES;
+        while !rest.is_empty() {
+            let (value, remaining) = decode_bytes(rest).unwrap();
+            values.push(value);
+            rest = remaining;
+        }
+        values
+    }
+
+    /// Feeds `chunks` in turn, collecting every value decoded along the way
+    fn decode_chunks<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> Vec<Value> {
+        let mut decoder = StreamDecoder::new();
+        let mut values = vec![];
+        for chunk in chunks {
+            decoder.feed(chunk);
+            while let Decoded::Complete(value) = decoder.decode().unwrap() {
+                values.push(value);
+            }
+        }
+        assert!(decoder.buffered().is_empty());
+        values
+    }
+
+    fn scan_error(limits: DecodeLimits, input: &[u8]) -> DecodeError {
+        let mut decoder = StreamDecoder::with_limits(limits);
+        decoder.feed(input);
+        decoder.decode().unwrap_err()
+    }
+
+    #[test]
+    fn decodes_input_split_at_every_byte() {
+        assert_eq!(decode_chunks(VALUES.chunks(1)), expected());
+    }
+
+    #[test]
+    fn decodes_input_split_anywhere() {
+        for split in 0..=VALUES.len() {
+            let (first, second) = VALUES.split_at(split);
+            assert_eq!(
+                decode_chunks([first, second]),
+                expected(),
+                "split at {split}"
+            );
+        }
+    }
+
+    #[test]
+    fn needs_more_until_a_value_is_complete() {
+        let mut decoder = StreamDecoder::new();
+        assert_eq!(decoder.decode(), Ok(Decoded::NeedMore));
+        decoder.feed(b"l5:ab");
+        assert_eq!(decoder.decode(), Ok(Decoded::NeedMore));
+        decoder.feed(b"cde");
+        assert_eq!(decoder.decode(), Ok(Decoded::NeedMore));
+        decoder.feed(b"e");
+        assert_eq!(
+            decoder.decode(),
+            Ok(Decoded::Complete(Value::List(vec![Value::from("abcde")])))
+        );
+    }
+
+    #[test]
+    fn keeps_trailing_values_buffered() {
+        let mut decoder = StreamDecoder::new();
+        decoder.feed(b"i1ei2");
+        assert_eq!(decoder.decode(), Ok(Decoded::Complete(Value::Int(1))));
+        assert_eq!(decoder.buffered(), b"i2");
+        assert_eq!(decoder.decode(), Ok(Decoded::NeedMore));
+        decoder.feed(b"e");
+        assert_eq!(decoder.next_value_bytes(), Ok(Some(b"i2e".to_vec())));
+        assert_eq!(decoder.decode(), Ok(Decoded::NeedMore));
+    }
+
+    #[test]
+    fn reports_bad_bytes() {
+        let limits = DecodeLimits::default();
+        assert_eq!(scan_error(limits, b"x").offset(), 0);
+        assert_eq!(scan_error(limits, b"l3x").offset(), 2);
+        assert!(matches!(
+            scan_error(limits, b"99999999999999999999999:"),
+            DecodeError::InvalidLength { .. }
+        ));
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limits = DecodeLimits {
+            max_depth: 1,
+            max_string_length: 2,
+            max_elements: 2,
+            max_input_size: 4,
+        };
+        assert_eq!(
+            scan_error(limits, b"ll"),
+            DecodeError::DepthLimitExceeded {
+                offset: 1,
+                limit: 1
+            }
+        );
+        assert_eq!(
+            scan_error(limits, b"3:"),
+            DecodeError::StringTooLong {
+                offset: 1,
+                length: 3,
+                limit: 2
+            }
+        );
+        assert_eq!(
+            scan_error(
+                DecodeLimits {
+                    max_input_size: 8,
+                    ..limits
+                },
+                b"li1ei"
+            ),
+            DecodeError::TooManyElements {
+                offset: 4,
+                limit: 2
+            }
+        );
+        assert_eq!(
+            scan_error(limits, b"i1234e"),
+            DecodeError::InputTooLarge {
+                offset: 4,
+                limit: 4
+            }
+        );
+    }
+}
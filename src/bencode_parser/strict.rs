@@ -0,0 +1,156 @@
+use std::fmt;
+
+use super::{DecodeError, Decoder, Value};
+
+/// Encoding that decodes fine but is not the canonical form, so re-encoding it gives different
+/// bytes (and for the info dict, a different info hash)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// Dictionary key that sorts before the key preceding it
+    UnsortedKey,
+    /// Dictionary key equal to the key preceding it
+    DuplicateKey,
+    /// Integer with leading zeros, e.g. `i03e`
+    IntegerLeadingZero,
+    /// `i-0e`
+    NegativeZero,
+    /// Byte string length with leading zeros, e.g. `03:abc`
+    LengthLeadingZero,
+    /// Byte string length with a `+` sign, e.g. `+3:abc`
+    LengthPlusSign,
+    /// Bytes left over after the top-level value
+    TrailingData,
+}
+
+impl fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            ViolationKind::UnsortedKey => "dictionary key is not in sorted order",
+            ViolationKind::DuplicateKey => "dictionary key is repeated",
+            ViolationKind::IntegerLeadingZero => "integer has leading zeros",
+            ViolationKind::NegativeZero => "integer is negative zero",
+            ViolationKind::LengthLeadingZero => "byte string length has leading zeros",
+            ViolationKind::LengthPlusSign => "byte string length has a `+` sign",
+            ViolationKind::TrailingData => "data after the end of the value",
+        };
+        f.write_str(message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Violation {
+    pub offset: usize,
+    pub kind: ViolationKind,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at byte {}: {}", self.offset, self.kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StrictError {
+    /// The input is not bencode at all
+    Decode(DecodeError),
+    /// The input is bencode, but not in canonical form
+    NonCanonical(Vec<Violation>),
+}
+
+impl fmt::Display for StrictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StrictError::Decode(err) => err.fmt(f),
+            StrictError::NonCanonical(violations) => {
+                write!(f, "{} non-canonical encoding(s)", violations.len())?;
+                violations
+                    .iter()
+                    .try_for_each(|violation| write!(f, "\n  {violation}"))
+            }
+        }
+    }
+}
+
+impl std::error::Error for StrictError {}
+
+impl From<DecodeError> for StrictError {
+    fn from(err: DecodeError) -> Self {
+        StrictError::Decode(err)
+    }
+}
+
+/// Decodes the whole input, collecting every non-canonical encoding in it
+pub fn lint(val: &[u8]) -> Result<Vec<Violation>, DecodeError> {
+    let mut decoder = Decoder::strict(val);
+    decoder.value()?;
+    if !decoder.rest().is_empty() {
+        decoder.report(decoder.pos, ViolationKind::TrailingData);
+    }
+    Ok(decoder.violations.unwrap_or_default())
+}
+
+/// Like [`decode_bytes`](super::decode_bytes), but rejects values that are not canonical
+pub fn decode_strict(val: &[u8]) -> Result<(Value, &[u8]), StrictError> {
+    let mut decoder = Decoder::strict(val);
+    let value = decoder.value()?;
+    match decoder.violations {
+        Some(violations) if !violations.is_empty() => Err(StrictError::NonCanonical(violations)),
+        _ => Ok((value.to_owned_value(), &val[decoder.pos..])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violations(input: &[u8]) -> Vec<(usize, ViolationKind)> {
+        lint(input)
+            .unwrap()
+            .into_iter()
+            .map(|violation| (violation.offset, violation.kind))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_canonical_input() {
+        assert_eq!(violations(b"d1:ai-1e1:bl0:i0eee"), []);
+    }
+
+    #[test]
+    fn reports_each_violation_kind() {
+        use ViolationKind::*;
+        assert_eq!(violations(b"d1:bi0e1:ai0ee"), [(7, UnsortedKey)]);
+        assert_eq!(violations(b"d1:ai0e1:ai0ee"), [(7, DuplicateKey)]);
+        assert_eq!(
+            violations(b"li03ei-03ee"),
+            [(1, IntegerLeadingZero), (5, IntegerLeadingZero)]
+        );
+        assert_eq!(violations(b"i-0e"), [(0, NegativeZero)]);
+        assert_eq!(violations(b"l03:abce"), [(1, LengthLeadingZero)]);
+        assert_eq!(violations(b"+3:abc"), [(0, LengthPlusSign)]);
+        assert_eq!(violations(b"i1ei2e"), [(3, TrailingData)]);
+    }
+
+    #[test]
+    fn reports_every_violation_in_order() {
+        use ViolationKind::*;
+        assert_eq!(
+            violations(b"d1:bi01e1:a+0:e"),
+            [
+                (4, IntegerLeadingZero),
+                (8, UnsortedKey),
+                (11, LengthPlusSign)
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_only_canonical_input() {
+        assert_eq!(decode_strict(b"i1ei2e"), Ok((Value::Int(1), &b"i2e"[..])));
+        assert!(matches!(
+            decode_strict(b"i01e"),
+            Err(StrictError::NonCanonical(violations)) if violations.len() == 1
+        ));
+        assert!(matches!(decode_strict(b"i1"), Err(StrictError::Decode(_))));
+    }
+}
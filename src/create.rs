@@ -0,0 +1,618 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{ensure, Context, Result};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    bencode_parser::Value,
+    merkle::{self, Hash},
+    storage::{FileAttributes, FileSpan, Storage},
+    torrent::MetaVersion,
+    verify::{file_checksums, Checksums},
+    DEFAULT_BLOCK_LENGTH,
+};
+
+/// Largest piece length picked automatically
+const MAX_AUTO_PIECE_LENGTH: u32 = 16 * 1024 * 1024;
+/// Automatic piece lengths grow until the torrent has at most this many pieces
+const TARGET_PIECE_COUNT: u64 = 1500;
+
+/// Authors a metainfo file from a file or a directory on disk
+#[derive(Debug, Clone)]
+pub struct TorrentBuilder {
+    root: PathBuf,
+    piece_length: Option<u32>,
+    trackers: Vec<Vec<String>>,
+    comment: Option<String>,
+    created_by: Option<String>,
+    creation_date: Option<u64>,
+    private: bool,
+    source: Option<String>,
+    web_seeds: Vec<String>,
+    ignore: Vec<String>,
+    version: MetaVersion,
+    checksums: bool,
+}
+
+impl TorrentBuilder {
+    /// Builder for the content at `root`, stamped with the current time and this program
+    pub fn new(root: impl Into<PathBuf>) -> TorrentBuilder {
+        let creation_date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .ok();
+        TorrentBuilder {
+            root: root.into(),
+            piece_length: None,
+            trackers: vec![],
+            comment: None,
+            created_by: Some(format!("bittorrust {}", env!("CARGO_PKG_VERSION"))),
+            creation_date,
+            private: false,
+            source: None,
+            web_seeds: vec![],
+            ignore: vec![],
+            version: MetaVersion::V1,
+            checksums: false,
+        }
+    }
+    /// Fixed piece length, a power of two of at least 16 KiB, instead of one picked from
+    /// the content size
+    pub fn piece_length(mut self, piece_length: u32) -> Self {
+        self.piece_length = Some(piece_length);
+        self
+    }
+    /// Adds a tracker in a tier of its own, the first tracker becomes `announce`
+    pub fn announce(self, tracker: impl Into<String>) -> Self {
+        self.announce_tier(vec![tracker.into()])
+    }
+    /// Adds a tier of trackers that clients try in random order
+    /// [spec](http://bittorrent.org/beps/bep_0012.html)
+    pub fn announce_tier(mut self, tier: Vec<String>) -> Self {
+        if !tier.is_empty() {
+            self.trackers.push(tier);
+        }
+        self
+    }
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+    pub fn created_by(mut self, created_by: Option<String>) -> Self {
+        self.created_by = created_by;
+        self
+    }
+    /// Seconds since the Unix epoch, `None` to leave the date out
+    pub fn creation_date(mut self, creation_date: Option<u64>) -> Self {
+        self.creation_date = creation_date;
+        self
+    }
+    /// Private torrents only get peers from their trackers
+    /// [spec](http://bittorrent.org/beps/bep_0027.html)
+    pub fn private(mut self, private: bool) -> Self {
+        self.private = private;
+        self
+    }
+    /// Stored in the info dict, so the same content gets a different info hash per source
+    pub fn source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+    /// HTTP server holding a copy of the content
+    /// [spec](http://bittorrent.org/beps/bep_0019.html)
+    pub fn web_seed(mut self, url: impl Into<String>) -> Self {
+        self.web_seeds.push(url.into());
+        self
+    }
+    /// Hash schemes to include, v2 and hybrid torrents need a piece length of 16 KiB or more
+    /// [spec](http://bittorrent.org/beps/bep_0052.html)
+    pub fn meta_version(mut self, version: MetaVersion) -> Self {
+        self.version = version;
+        self
+    }
+    /// Stores the MD5 (`md5sum`) and SHA-1 (`sha1`) of every file next to its v1 entry, so the
+    /// files can be checked with common tools; v2-only torrents have no such entries
+    pub fn checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+    /// Skips files and directories matching `pattern` while walking a directory.
+    ///
+    /// `*` matches any run of characters and `?` any one character. Patterns containing `/`
+    /// match the path relative to the root, others match the file or directory name.
+    pub fn ignore(mut self, pattern: impl Into<String>) -> Self {
+        self.ignore.push(pattern.into());
+        self
+    }
+
+    /// Walks and hashes the content, returning the bencoded metainfo
+    pub fn build(&self) -> Result<Vec<u8>> {
+        ensure!(
+            !self.checksums || self.version != MetaVersion::V2,
+            "file checksums need the v1 file entries, which v2-only torrents lack"
+        );
+        let name = self
+            .root
+            .file_name()
+            .and_then(|name| name.to_str())
+            .with_context(|| format!("{} has no UTF-8 file name", self.root.display()))?
+            .to_string();
+        let metadata = fs::metadata(&self.root)
+            .with_context(|| format!("failed to read {}", self.root.display()))?;
+        let is_dir = metadata.is_dir();
+        // a single file is laid out as a directory holding just that file
+        let files = if is_dir {
+            let files = self.walk()?;
+            ensure!(
+                !files.is_empty(),
+                "{} has no files to share",
+                self.root.display()
+            );
+            files
+        } else {
+            vec![(vec![name.clone()], metadata.len())]
+        };
+
+        let total_length = files.iter().map(|(_, length)| length).sum();
+        let piece_length = match self.piece_length {
+            Some(piece_length) => {
+                ensure!(
+                    piece_length >= DEFAULT_BLOCK_LENGTH && piece_length.is_power_of_two(),
+                    "piece length should be a power of two of at least 16 KiB, got {piece_length}"
+                );
+                piece_length
+            }
+            None => auto_piece_length(total_length),
+        };
+        let file_path = |path: &[String]| {
+            if is_dir {
+                path.iter().fold(self.root.clone(), |p, c| p.join(c))
+            } else {
+                self.root.clone()
+            }
+        };
+
+        let mut info = BTreeMap::new();
+        let mut piece_layers = BTreeMap::new();
+        if self.version != MetaVersion::V1 {
+            let mut offset = 0;
+            let spans = files
+                .iter()
+                .map(|(path, length)| {
+                    let span = FileSpan {
+                        path: file_path(path),
+                        offset,
+                        length: *length,
+                        padding: false,
+                        attributes: FileAttributes::default(),
+                    };
+                    offset += length;
+                    span
+                })
+                .collect();
+            let roots = hash_v2(
+                &Storage::from_spans(spans, piece_length),
+                piece_length,
+                &mut piece_layers,
+            )?;
+            let mut file_tree = BTreeMap::new();
+            for ((path, length), root) in files.iter().zip(roots) {
+                let mut leaf = BTreeMap::new();
+                leaf.insert(b"length".to_vec(), Value::Int(*length as i64));
+                if let Some(root) = root {
+                    leaf.insert(b"pieces root".to_vec(), Value::from(&root[..]));
+                }
+                insert_into_tree(&mut file_tree, path, Value::Dict(leaf));
+            }
+            info.insert(b"file tree".to_vec(), Value::Dict(file_tree));
+            info.insert(b"meta version".to_vec(), Value::Int(2));
+        }
+        if self.version != MetaVersion::V2 {
+            let mut spans = vec![];
+            let mut offset = 0;
+            for (index, (path, length)) in files.iter().enumerate() {
+                spans.push(FileSpan {
+                    path: file_path(path),
+                    offset,
+                    length: *length,
+                    padding: false,
+                    attributes: FileAttributes::default(),
+                });
+                offset += length;
+                // hybrid torrents start every file on a piece boundary, so that v1 pieces
+                // line up with the per-file v2 ones
+                let gap = offset.next_multiple_of(piece_length as u64) - offset;
+                if self.version == MetaVersion::Hybrid && gap > 0 && index + 1 < files.len() {
+                    spans.push(FileSpan {
+                        path: [".pad", &gap.to_string()].iter().collect(),
+                        offset,
+                        length: gap,
+                        padding: true,
+                        attributes: FileAttributes::default(),
+                    });
+                    offset += gap;
+                }
+            }
+            let checksums = if self.checksums {
+                parallel_map(spans.len(), |index| {
+                    let span = &spans[index];
+                    if span.padding {
+                        return Ok(None);
+                    }
+                    file_checksums(&span.path)
+                        .map(Some)
+                        .with_context(|| format!("failed to read {}", span.path.display()))
+                })?
+            } else {
+                vec![None; spans.len()]
+            };
+            let add_checksums = |dict: &mut BTreeMap<Vec<u8>, Value>,
+                                 checksums: Option<Checksums>| {
+                if let Some(checksums) = checksums {
+                    dict.insert(b"md5sum".to_vec(), Value::from(hex::encode(checksums.md5)));
+                    dict.insert(b"sha1".to_vec(), Value::from(&checksums.sha1[..]));
+                }
+            };
+            if is_dir {
+                let entries = spans.iter().zip(&checksums).map(|(span, checksums)| {
+                    let mut entry = BTreeMap::new();
+                    entry.insert(b"length".to_vec(), Value::Int(span.length as i64));
+                    let components = span
+                        .path
+                        .strip_prefix(&self.root)
+                        .unwrap_or(&span.path)
+                        .iter()
+                        .map(|c| Value::from(c.to_string_lossy().as_ref()));
+                    entry.insert(b"path".to_vec(), Value::List(components.collect()));
+                    if span.padding {
+                        entry.insert(b"attr".to_vec(), Value::from("p"));
+                    }
+                    add_checksums(&mut entry, *checksums);
+                    Value::Dict(entry)
+                });
+                info.insert(b"files".to_vec(), Value::List(entries.collect()));
+            } else {
+                info.insert(b"length".to_vec(), Value::Int(metadata.len() as i64));
+                add_checksums(&mut info, checksums[0]);
+            }
+            let pieces = hash_v1(&Storage::from_spans(spans, piece_length), piece_length)?;
+            info.insert(b"pieces".to_vec(), Value::Bytes(pieces));
+        }
+
+        info.insert(b"name".to_vec(), Value::from(name));
+        info.insert(b"piece length".to_vec(), Value::Int(piece_length as i64));
+        if self.private {
+            info.insert(b"private".to_vec(), Value::Int(1));
+        }
+        if let Some(source) = &self.source {
+            info.insert(b"source".to_vec(), Value::from(source.as_str()));
+        }
+
+        let mut metainfo = BTreeMap::new();
+        // without trackers the torrent relies on web seeds or peers found some other way
+        if let Some(announce) = self.trackers.first().and_then(|tier| tier.first()) {
+            metainfo.insert(b"announce".to_vec(), Value::from(announce.as_str()));
+        }
+        if self.trackers.iter().flatten().count() > 1 {
+            let tiers = self
+                .trackers
+                .iter()
+                .map(|tier| Value::List(tier.iter().map(|t| Value::from(t.as_str())).collect()))
+                .collect();
+            metainfo.insert(b"announce-list".to_vec(), Value::List(tiers));
+        }
+        if let Some(comment) = &self.comment {
+            metainfo.insert(b"comment".to_vec(), Value::from(comment.as_str()));
+        }
+        if let Some(created_by) = &self.created_by {
+            metainfo.insert(b"created by".to_vec(), Value::from(created_by.as_str()));
+        }
+        if let Some(creation_date) = self.creation_date {
+            metainfo.insert(b"creation date".to_vec(), Value::Int(creation_date as i64));
+        }
+        if !self.web_seeds.is_empty() {
+            let urls = self.web_seeds.iter().map(|url| Value::from(url.as_str()));
+            metainfo.insert(b"url-list".to_vec(), Value::List(urls.collect()));
+        }
+        if !piece_layers.is_empty() {
+            metainfo.insert(b"piece layers".to_vec(), Value::Dict(piece_layers));
+        }
+        metainfo.insert(b"info".to_vec(), Value::Dict(info));
+        Ok(Value::Dict(metainfo).encode())
+    }
+
+    /// Files under the root as path components and lengths, sorted by path
+    fn walk(&self) -> Result<Vec<(Vec<String>, u64)>> {
+        let mut files = vec![];
+        let mut pending = vec![vec![]];
+        while let Some(dir) = pending.pop() {
+            let dir_path = dir
+                .iter()
+                .fold(self.root.clone(), |p: PathBuf, c| p.join(c));
+            let entries = fs::read_dir(&dir_path)
+                .with_context(|| format!("failed to read {}", dir_path.display()))?;
+            for entry in entries {
+                let entry = entry?;
+                let name = entry.file_name().into_string().map_err(|name| {
+                    anyhow::anyhow!("{} is not UTF-8", Path::new(&name).display())
+                })?;
+                let mut path: Vec<String> = dir.clone();
+                path.push(name);
+                if self.is_ignored(&path) {
+                    continue;
+                }
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    pending.push(path);
+                } else if file_type.is_file() {
+                    files.push((path, entry.metadata()?.len()));
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+    fn is_ignored(&self, path: &[String]) -> bool {
+        let relative = path.join("/");
+        let name = path.last().map(String::as_str).unwrap_or_default();
+        self.ignore.iter().any(|pattern| {
+            let target = if pattern.contains('/') {
+                &relative
+            } else {
+                name
+            };
+            wildcard_match(pattern.as_bytes(), target.as_bytes())
+        })
+    }
+}
+
+/// Smallest power of two from 16 KiB up that keeps the piece count near
+/// [`TARGET_PIECE_COUNT`]
+fn auto_piece_length(total_length: u64) -> u32 {
+    let mut piece_length = DEFAULT_BLOCK_LENGTH;
+    while piece_length < MAX_AUTO_PIECE_LENGTH
+        && total_length.div_ceil(piece_length as u64) > TARGET_PIECE_COUNT
+    {
+        piece_length *= 2;
+    }
+    piece_length
+}
+
+/// SHA-1 of every piece, concatenated
+fn hash_v1(storage: &Storage, piece_length: u32) -> Result<Vec<u8>> {
+    let total_length = storage.total_length();
+    let piece_count = total_length.div_ceil(piece_length as u64) as usize;
+    let hashes = parallel_map(piece_count, |index| {
+        let offset = index as u64 * piece_length as u64;
+        let length = (total_length - offset).min(piece_length as u64);
+        let data = storage
+            .read_range(offset, length)
+            .with_context(|| format!("failed to read piece {index} from the content"))?;
+        Ok(Sha1::digest(&data))
+    })?;
+    Ok(hashes.concat())
+}
+
+/// `pieces root` of every file, `None` for empty ones, adding the piece layer of every file
+/// longer than a piece to `piece_layers`
+fn hash_v2(
+    storage: &Storage,
+    piece_length: u32,
+    piece_layers: &mut BTreeMap<Vec<u8>, Value>,
+) -> Result<Vec<Option<Hash>>> {
+    // v2 pieces never span files, so hash each file's pieces separately
+    let pieces: Vec<(usize, u64)> = storage
+        .files()
+        .iter()
+        .enumerate()
+        .flat_map(|(index, file)| {
+            let count = file.length.div_ceil(piece_length as u64);
+            (0..count).map(move |piece| (index, piece))
+        })
+        .collect();
+    let hashes = parallel_map(pieces.len(), |item| {
+        let (index, piece) = pieces[item];
+        let file = &storage.files()[index];
+        let start = piece * piece_length as u64;
+        let length = (file.length - start).min(piece_length as u64);
+        let data = storage
+            .read_range(file.offset + start, length)
+            .with_context(|| format!("failed to read {}", file.path.display()))?;
+        if file.length <= piece_length as u64 {
+            Ok(merkle::small_file_root(&data))
+        } else {
+            Ok(merkle::piece_hash(&data, piece_length))
+        }
+    })?;
+
+    let mut hashes = hashes.into_iter();
+    let roots = storage
+        .files()
+        .iter()
+        .map(|file| {
+            let count = file.length.div_ceil(piece_length as u64) as usize;
+            let layer: Vec<Hash> = hashes.by_ref().take(count).collect();
+            match count {
+                0 => None,
+                1 if file.length <= piece_length as u64 => Some(layer[0]),
+                _ => {
+                    let root = merkle::file_root(&layer, piece_length);
+                    piece_layers.insert(root.to_vec(), Value::Bytes(layer.concat()));
+                    Some(root)
+                }
+            }
+        })
+        .collect();
+    Ok(roots)
+}
+
+/// Runs `job` for every index in `0..count`, split evenly across the cores, returning the
+/// results in index order
+pub(crate) fn parallel_map<T: Send>(
+    count: usize,
+    job: impl Fn(usize) -> Result<T> + Sync,
+) -> Result<Vec<T>> {
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let per_worker = count.div_ceil(workers).max(1);
+    let job = &job;
+    let chunks: Vec<Result<Vec<T>>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..count)
+            .step_by(per_worker)
+            .map(|first| {
+                scope.spawn(move || (first..(first + per_worker).min(count)).map(job).collect())
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("hashing thread panicked"))
+            .collect()
+    });
+    Ok(chunks
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+/// Adds a file to a v2 `file tree`, creating the directories on its path
+fn insert_into_tree(tree: &mut BTreeMap<Vec<u8>, Value>, path: &[String], leaf: Value) {
+    let Some((first, rest)) = path.split_first() else {
+        return;
+    };
+    let key = first.as_bytes().to_vec();
+    if rest.is_empty() {
+        tree.insert(key, Value::from_iter([("", leaf)]));
+    } else if let Value::Dict(dir) = tree
+        .entry(key)
+        .or_insert_with(|| Value::Dict(BTreeMap::new()))
+    {
+        insert_into_tree(dir, rest, leaf);
+    }
+}
+
+/// Matches `text` against a pattern where `*` is any run of bytes and `?` any one byte
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and the text position it is currently matched up to
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::TempDir, torrent::Torrent, verify};
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match(b"*.tmp", b"a.tmp"));
+        assert!(wildcard_match(b"*.tmp", b".tmp"));
+        assert!(!wildcard_match(b"*.tmp", b"a.tmp.txt"));
+        assert!(wildcard_match(b"a?c", b"abc"));
+        assert!(!wildcard_match(b"a?c", b"ac"));
+        assert!(wildcard_match(b"*a*b*", b"xxaxxbxx"));
+        assert!(!wildcard_match(b"*a*b", b"xxbxxa"));
+        assert!(wildcard_match(b"**", b""));
+        assert!(!wildcard_match(b"", b"a"));
+    }
+
+    #[test]
+    fn picks_piece_length_from_size() {
+        assert_eq!(auto_piece_length(0), DEFAULT_BLOCK_LENGTH);
+        assert_eq!(
+            auto_piece_length(TARGET_PIECE_COUNT * DEFAULT_BLOCK_LENGTH as u64),
+            DEFAULT_BLOCK_LENGTH
+        );
+        assert_eq!(
+            auto_piece_length(TARGET_PIECE_COUNT * DEFAULT_BLOCK_LENGTH as u64 + 1),
+            2 * DEFAULT_BLOCK_LENGTH
+        );
+        assert_eq!(auto_piece_length(u64::MAX), MAX_AUTO_PIECE_LENGTH);
+    }
+
+    #[test]
+    fn builds_a_trackerless_single_file_torrent() {
+        let dir = TempDir::new("create-single");
+        let file = dir.write("data.bin", 40000);
+        let bytes = TorrentBuilder::new(&file)
+            .piece_length(DEFAULT_BLOCK_LENGTH)
+            .web_seed("http://example.com/")
+            .build()
+            .unwrap();
+        let torrent = Torrent::from_bytes(&bytes).unwrap();
+        assert_eq!(torrent.tracker(), None);
+        assert_eq!(torrent.url_list, ["http://example.com/"]);
+        assert_eq!(torrent.info.name, "data.bin");
+        assert_eq!(torrent.info.length, Some(40000));
+        assert_eq!(torrent.info.piece_count(), 3);
+        assert!(verify::verify(&torrent, &file).unwrap().is_complete());
+    }
+
+    #[test]
+    fn builds_a_hybrid_directory_torrent() {
+        let dir = TempDir::new("create-hybrid");
+        dir.write("content/a.bin", 20000);
+        dir.write("content/sub/b.bin", 100);
+        dir.write("content/skip.tmp", 10);
+        let bytes = TorrentBuilder::new(dir.0.join("content"))
+            .piece_length(DEFAULT_BLOCK_LENGTH)
+            .announce_tier(vec!["http://a/announce".into(), "http://b/announce".into()])
+            .announce("http://c/announce")
+            .meta_version(MetaVersion::Hybrid)
+            .ignore("*.tmp")
+            .build()
+            .unwrap();
+        let torrent = Torrent::from_bytes(&bytes).unwrap();
+        assert_eq!(torrent.info.version(), MetaVersion::Hybrid);
+        assert_eq!(
+            torrent.trackers(),
+            [
+                vec!["http://a/announce", "http://b/announce"],
+                vec!["http://c/announce"]
+            ]
+        );
+        let paths: Vec<_> = torrent
+            .info
+            .files
+            .iter()
+            .flatten()
+            .map(|file| (file.path.join("/"), file.is_padding()))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("a.bin".to_string(), false),
+                (".pad/12768".to_string(), true),
+                ("sub/b.bin".to_string(), false)
+            ]
+        );
+        assert!(verify::verify(&torrent, &dir.0).unwrap().is_complete());
+    }
+}
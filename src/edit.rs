@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    bencode_parser::{self, Value},
+    merkle::Hash,
+    torrent::{MetainfoError, Torrent},
+};
+
+/// Changes the fields of a metainfo file outside `info`, such as trackers, comment and web
+/// seeds.
+///
+/// The `info` dictionary is written back byte for byte rather than re-serialized, so the info
+/// hash stays the same even for dictionaries that are not canonical or hold keys this crate
+/// does not know. Keys outside `info` that are not edited are kept as they are.
+#[derive(Debug, Clone)]
+pub struct TorrentEditor {
+    /// The metainfo dictionary without `info`
+    metainfo: BTreeMap<Vec<u8>, Value>,
+    raw_info: Vec<u8>,
+    info_hash: [u8; 20],
+    info_hash_v2: Option<Hash>,
+}
+
+impl TorrentEditor {
+    pub fn from_bytes(bytes: &[u8]) -> Result<TorrentEditor, MetainfoError> {
+        let torrent = Torrent::from_bytes(bytes)?;
+        let (decoded, _) = bencode_parser::decode_bytes(bytes)?;
+        let Value::Dict(mut metainfo) = decoded else {
+            unreachable!("a valid torrent is a dictionary")
+        };
+        metainfo.remove(b"info".as_slice());
+        Ok(TorrentEditor {
+            metainfo,
+            info_hash: torrent.info_hash(),
+            info_hash_v2: torrent.info_hash_v2(),
+            raw_info: torrent.raw_info,
+        })
+    }
+    /// The trackers, read as [`Torrent::trackers`] reads them
+    pub fn trackers(&self) -> Vec<Vec<String>> {
+        let tiers = self
+            .metainfo
+            .get(b"announce-list".as_slice())
+            .and_then(Value::as_list)
+            .unwrap_or_default();
+        if tiers.is_empty() {
+            return self
+                .string(b"announce")
+                .into_iter()
+                .map(|url| vec![url])
+                .collect();
+        }
+        tiers
+            .iter()
+            .map(|tier| strings(tier.as_list().unwrap_or_default()))
+            .collect()
+    }
+    /// Replaces the trackers, the first one becomes `announce` and `announce-list` is only
+    /// kept when there is more than one tracker. Without any, both keys are removed and the
+    /// torrent is left trackerless.
+    pub fn set_trackers(&mut self, tiers: Vec<Vec<String>>) -> &mut Self {
+        let tiers: Vec<Vec<String>> = tiers.into_iter().filter(|tier| !tier.is_empty()).collect();
+        match tiers.first().and_then(|tier| tier.first()) {
+            Some(announce) => self.set(b"announce", Value::from(announce.as_str())),
+            None => self.remove(b"announce"),
+        };
+        if tiers.iter().flatten().count() > 1 {
+            let tiers = tiers
+                .iter()
+                .map(|tier| Value::List(tier.iter().map(|t| Value::from(t.as_str())).collect()))
+                .collect();
+            self.set(b"announce-list", Value::List(tiers));
+        } else {
+            self.remove(b"announce-list");
+        }
+        self
+    }
+    /// Adds a tier of trackers after the existing ones
+    pub fn add_tracker_tier(&mut self, tier: Vec<String>) -> &mut Self {
+        let mut tiers = self.trackers();
+        tiers.push(tier);
+        self.set_trackers(tiers)
+    }
+    /// Removes `url` from every tier, dropping tiers left empty
+    pub fn remove_tracker(&mut self, url: &str) -> &mut Self {
+        let mut tiers = self.trackers();
+        for tier in &mut tiers {
+            tier.retain(|tracker| tracker != url);
+        }
+        self.set_trackers(tiers)
+    }
+    /// `None` removes the comment
+    pub fn comment(&mut self, comment: Option<String>) -> &mut Self {
+        self.set_optional(b"comment", comment.map(Value::from))
+    }
+    /// `None` removes the creator
+    pub fn created_by(&mut self, created_by: Option<String>) -> &mut Self {
+        self.set_optional(b"created by", created_by.map(Value::from))
+    }
+    /// Seconds since the Unix epoch, `None` removes the date
+    pub fn creation_date(&mut self, creation_date: Option<u64>) -> &mut Self {
+        self.set_optional(
+            b"creation date",
+            creation_date.map(|date| Value::Int(date as i64)),
+        )
+    }
+    /// `url-list` web seeds, also when given as a single string
+    pub fn web_seeds(&self) -> Vec<String> {
+        match self.metainfo.get(b"url-list".as_slice()) {
+            Some(Value::List(urls)) => strings(urls),
+            Some(url) => url.as_str().map(str::to_string).into_iter().collect(),
+            None => vec![],
+        }
+    }
+    /// Replaces the `url-list` web seeds, removing the key when `urls` is empty
+    pub fn set_web_seeds(&mut self, urls: Vec<String>) -> &mut Self {
+        let urls =
+            (!urls.is_empty()).then(|| Value::List(urls.into_iter().map(Value::from).collect()));
+        self.set_optional(b"url-list", urls)
+    }
+    pub fn add_web_seed(&mut self, url: impl Into<String>) -> &mut Self {
+        let mut urls = self.web_seeds();
+        urls.push(url.into());
+        self.set_web_seeds(urls)
+    }
+    pub fn remove_web_seed(&mut self, url: &str) -> &mut Self {
+        let mut urls = self.web_seeds();
+        urls.retain(|seed| seed != url);
+        self.set_web_seeds(urls)
+    }
+    /// Bencoded metainfo with the original `info` bytes, checked to load and to keep the
+    /// info hashes it was read with
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut metainfo = self.metainfo.clone();
+        // a placeholder so the keys are written in order, replaced by the raw bytes below
+        metainfo.insert(b"info".to_vec(), Value::Int(0));
+        let mut out = vec![b'd'];
+        for (key, value) in metainfo {
+            bencode_parser::encode_byte_string(&key, &mut out);
+            if key == b"info" {
+                out.extend(&self.raw_info);
+            } else {
+                value.encode_to(&mut out);
+            }
+        }
+        out.push(b'e');
+        let edited = Torrent::from_bytes(&out).context("the edited metainfo is invalid")?;
+        ensure!(
+            edited.info_hash() == self.info_hash && edited.info_hash_v2() == self.info_hash_v2,
+            "editing changed the info hash"
+        );
+        Ok(out)
+    }
+    fn string(&self, key: &[u8]) -> Option<String> {
+        self.metainfo
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    }
+    fn set(&mut self, key: &[u8], value: Value) {
+        self.metainfo.insert(key.to_vec(), value);
+    }
+    fn remove(&mut self, key: &[u8]) {
+        self.metainfo.remove(key);
+    }
+    fn set_optional(&mut self, key: &[u8], value: Option<Value>) -> &mut Self {
+        match value {
+            Some(value) => self.set(key, value),
+            None => self.remove(key),
+        }
+        self
+    }
+}
+
+/// The strings of a list, skipping anything else
+fn strings(values: &[Value]) -> Vec<String> {
+    values
+        .iter()
+        .filter_map(Value::as_str)
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use sha1::{Digest, Sha1};
+
+    use super::*;
+
+    /// Non-canonical `info`: unsorted keys, an integer with a leading zero and a key this
+    /// crate does not know
+    const INFO: &[u8] =
+        b"d4:name1:a12:piece lengthi16384e6:lengthi05e6:pieces20:aaaaaaaaaaaaaaaaaaaa1:zi1ee";
+
+    fn metainfo() -> Vec<u8> {
+        [
+            b"d8:announce8:http://a7:comment3:old7:unknownli1ee4:info".as_slice(),
+            INFO,
+            b"e",
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn keeps_the_info_hash() {
+        let info_hash: [u8; 20] = Sha1::digest(INFO).into();
+        let mut editor = TorrentEditor::from_bytes(&metainfo()).unwrap();
+        editor
+            .set_trackers(vec![vec!["http://b".into(), "http://c".into()]])
+            .comment(None)
+            .created_by(Some("me".into()))
+            .add_web_seed("http://ws/");
+        let edited = editor.to_bytes().unwrap();
+        let torrent = Torrent::from_bytes(&edited).unwrap();
+        assert_eq!(torrent.info_hash(), info_hash);
+        assert_eq!(torrent.info_bytes(), INFO);
+        assert_eq!(torrent.trackers(), [["http://b", "http://c"]]);
+        assert_eq!(torrent.comment, None);
+        assert_eq!(torrent.created_by.as_deref(), Some("me"));
+        assert_eq!(torrent.url_list, ["http://ws/"]);
+        // keys outside `info` that were not edited are kept
+        let (decoded, _) = bencode_parser::decode_bytes(&edited).unwrap();
+        assert_eq!(
+            decoded.get("unknown"),
+            Some(&Value::List(vec![Value::Int(1)]))
+        );
+    }
+
+    #[test]
+    fn edits_trackers_by_tier() {
+        let mut editor = TorrentEditor::from_bytes(&metainfo()).unwrap();
+        assert_eq!(editor.trackers(), [["http://a"]]);
+        editor.add_tracker_tier(vec!["http://b".into()]);
+        assert_eq!(editor.trackers(), [["http://a"], ["http://b"]]);
+        editor.remove_tracker("http://a");
+        assert_eq!(editor.trackers(), [["http://b"]]);
+        editor.remove_tracker("http://b");
+        assert_eq!(editor.trackers(), Vec::<Vec<String>>::new());
+        let torrent = Torrent::from_bytes(&editor.to_bytes().unwrap()).unwrap();
+        assert_eq!(torrent.tracker(), None);
+    }
+}
@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, ensure, Context, Result};
+use tokio::{io::AsyncWriteExt, net::TcpStream, sync::Mutex};
+
+use crate::{
+    merkle::{self, Hash, BLOCK_LENGTH},
+    metadata::read_message,
+    torrent::{Info, Torrent, V2File},
+};
+
+/// Message ids of the v2 hash exchange
+/// [spec](http://bittorrent.org/beps/bep_0052.html)
+pub const HASH_REQUEST_ID: u8 = 21;
+pub const HASHES_ID: u8 = 22;
+pub const HASH_REJECT_ID: u8 = 23;
+/// Most hashes a peer may be asked for in one request
+pub const MAX_HASHES_PER_REQUEST: u32 = 512;
+
+/// Hashes of one layer of a file's merkle tree, as asked for in a `hash request` and echoed
+/// back in the `hashes` and `hash reject` answers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashRequest {
+    pub pieces_root: Hash,
+    /// Layer of the requested hashes, counted up from the blocks at 0
+    pub base_layer: u32,
+    /// Position of the first hash within its layer, a multiple of `length`
+    pub index: u32,
+    /// Number of hashes, a power of two from 2 to [`MAX_HASHES_PER_REQUEST`]
+    pub length: u32,
+    /// Number of uncle hashes wanted above the requested ones, to prove them to the root
+    pub proof_layers: u32,
+}
+
+impl HashRequest {
+    const ENCODED_LENGTH: usize = 48;
+
+    /// The full message, length prefix included, under `message_id`
+    pub fn encode(&self, message_id: u8) -> Vec<u8> {
+        let mut message = Vec::with_capacity(Self::ENCODED_LENGTH + 5);
+        message.extend((Self::ENCODED_LENGTH as u32 + 1).to_be_bytes());
+        message.push(message_id);
+        message.extend(self.pieces_root);
+        for field in [self.base_layer, self.index, self.length, self.proof_layers] {
+            message.extend(field.to_be_bytes());
+        }
+        message
+    }
+    pub fn from_payload(payload: &[u8]) -> Option<HashRequest> {
+        let payload = payload.get(..Self::ENCODED_LENGTH)?;
+        let field = |at: usize| u32::from_be_bytes(payload[at..at + 4].try_into().unwrap());
+        Some(HashRequest {
+            pieces_root: payload[..32].try_into().unwrap(),
+            base_layer: field(32),
+            index: field(36),
+            length: field(40),
+            proof_layers: field(44),
+        })
+    }
+}
+
+/// Answer to a [`HashRequest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hashes {
+    pub request: HashRequest,
+    /// The requested hashes, padding past the end of the file included
+    pub hashes: Vec<Hash>,
+    /// Uncle hashes from the layer above the requested ones upwards
+    pub proof: Vec<Hash>,
+}
+
+impl Hashes {
+    pub fn from_payload(payload: &[u8]) -> Option<Hashes> {
+        let request = HashRequest::from_payload(payload)?;
+        let nodes = payload[HashRequest::ENCODED_LENGTH..].chunks_exact(32);
+        if !nodes.remainder().is_empty() || nodes.len() < request.length as usize {
+            return None;
+        }
+        let mut hashes: Vec<Hash> = nodes.map(|hash| hash.try_into().unwrap()).collect();
+        let proof = hashes.split_off(request.length as usize);
+        Some(Hashes {
+            request,
+            hashes,
+            proof,
+        })
+    }
+    /// Whether the hashes, combined with the proof, lead up to `root`, the node `proof.len()`
+    /// layers above the subtree the hashes span
+    pub fn verify(&self, root: &Hash) -> bool {
+        let length = self.hashes.len();
+        if !length.is_power_of_two() {
+            return false;
+        }
+        let mut node = merkle::root(&self.hashes, length, [0; 32]);
+        let mut position = self.request.index as usize / length;
+        for uncle in &self.proof {
+            node = if position.is_multiple_of(2) {
+                merkle::hash_pair(&node, uncle)
+            } else {
+                merkle::hash_pair(uncle, &node)
+            };
+            position /= 2;
+        }
+        node == *root
+    }
+}
+
+/// Sends `request` and waits for the peer's answer, which must check out against `root`
+pub async fn request_hashes(
+    stream: &mut TcpStream,
+    request: HashRequest,
+    root: &Hash,
+) -> Result<Vec<Hash>> {
+    stream.write_all(&request.encode(HASH_REQUEST_ID)).await?;
+    let hash_count = (request.length + request.proof_layers) as usize;
+    let max_length = (HashRequest::ENCODED_LENGTH + 1 + hash_count * 32).max(BLOCK_LENGTH + 13);
+    loop {
+        let (id, payload) = read_message(stream, max_length).await?;
+        match id {
+            HASHES_ID => {
+                let hashes = Hashes::from_payload(&payload).context("invalid hashes message")?;
+                if hashes.request != request {
+                    continue;
+                }
+                ensure!(
+                    hashes.verify(root),
+                    "peer sent hashes for layer {} that do not match the merkle root",
+                    request.base_layer
+                );
+                return Ok(hashes.hashes);
+            }
+            HASH_REJECT_ID if HashRequest::from_payload(&payload) == Some(request) => {
+                bail!(
+                    "peer rejected the request for {} hashes of layer {} at {}",
+                    request.length,
+                    request.base_layer,
+                    request.index
+                )
+            }
+            _ => continue,
+        }
+    }
+}
+
+/// Expected leaf hashes of one piece's blocks
+#[derive(Debug, Clone)]
+pub struct PieceHashes {
+    leaves: Vec<Hash>,
+    /// Bytes of the piece inside its file, the rest of a hybrid piece is padding
+    file_bytes: u64,
+}
+
+impl PieceHashes {
+    /// Whether block `block_index` of the piece holds the data its leaf hash promises
+    pub fn check_block(&self, block_index: u32, data: &[u8]) -> bool {
+        let start = block_index as u64 * BLOCK_LENGTH as u64;
+        let in_file = self.file_bytes.saturating_sub(start).min(data.len() as u64) as usize;
+        let (content, padding) = data.split_at(in_file);
+        if padding.iter().any(|&byte| byte != 0) {
+            return false;
+        }
+        if in_file == 0 {
+            return true;
+        }
+        self.leaves
+            .get(block_index as usize)
+            .is_some_and(|leaf| *leaf == merkle::hash_block(content))
+    }
+}
+
+/// Checks the pieces of a v2 or hybrid torrent block by block against the files' merkle roots,
+/// fetching the hashes the metainfo lacks from the peer
+#[derive(Debug)]
+pub struct BlockVerifier {
+    info: Info,
+    /// Piece layers known so far, those from the metainfo and those fetched from the peer
+    piece_layers: Mutex<BTreeMap<Hash, Vec<Hash>>>,
+}
+
+impl BlockVerifier {
+    pub fn new(torrent: &Torrent) -> BlockVerifier {
+        let piece_layers = torrent
+            .piece_layers
+            .iter()
+            .map(|(root, layer)| {
+                let hashes = layer
+                    .chunks_exact(32)
+                    .map(|hash| hash.try_into().unwrap())
+                    .collect();
+                (*root, hashes)
+            })
+            .collect();
+        BlockVerifier {
+            info: torrent.info.clone(),
+            piece_layers: Mutex::new(piece_layers),
+        }
+    }
+    /// Leaf hashes of piece `piece_index`, proven against its piece layer hash, or against the
+    /// file's root for files no longer than a piece
+    pub async fn piece_hashes(
+        &self,
+        stream: &Mutex<TcpStream>,
+        piece_index: u32,
+    ) -> Result<PieceHashes> {
+        let piece = self
+            .info
+            .v2_piece(piece_index)
+            .with_context(|| format!("piece {piece_index} is not part of any v2 file"))?;
+        let pieces_root = piece
+            .file
+            .pieces_root
+            .context("empty files have no pieces")?;
+        let piece_length = self.info.piece_length;
+        let blocks_per_piece = piece_length / BLOCK_LENGTH as u32;
+        let (anchor, width) = if piece.file.length > piece_length as u64 {
+            let layer = self.piece_layer(stream, piece.file).await?;
+            (layer[piece.index as usize], blocks_per_piece)
+        } else {
+            let blocks = piece.file.length.div_ceil(BLOCK_LENGTH as u64) as u32;
+            (pieces_root, blocks.next_power_of_two())
+        };
+        let leaves = if width == 1 {
+            vec![anchor]
+        } else {
+            let subtree = Subtree {
+                pieces_root,
+                base_layer: 0,
+                index: piece.index * width,
+                width,
+            };
+            subtree
+                .request(&mut *stream.lock().await, width, &anchor)
+                .await?
+        };
+        Ok(PieceHashes {
+            leaves,
+            file_bytes: piece.length as u64,
+        })
+    }
+    /// Piece layer of a file longer than one piece, asked of the peer when the metainfo came
+    /// without it
+    async fn piece_layer(&self, stream: &Mutex<TcpStream>, file: &V2File) -> Result<Vec<Hash>> {
+        let pieces_root = file.pieces_root.context("empty files have no pieces")?;
+        // held throughout, so pieces of the same file wait for one fetch
+        let mut piece_layers = self.piece_layers.lock().await;
+        if let Some(layer) = piece_layers.get(&pieces_root) {
+            return Ok(layer.clone());
+        }
+        let layer = request_piece_layer(&mut *stream.lock().await, &self.info, file).await?;
+        piece_layers.insert(pieces_root, layer.clone());
+        Ok(layer)
+    }
+}
+
+/// Piece layer of a file longer than one piece, proven against its `pieces root`
+pub async fn request_piece_layer(
+    stream: &mut TcpStream,
+    info: &Info,
+    file: &V2File,
+) -> Result<Vec<Hash>> {
+    let pieces_root = file.pieces_root.context("empty files have no pieces")?;
+    let piece_length = info.piece_length;
+    let piece_count = file.length.div_ceil(piece_length as u64) as u32;
+    let subtree = Subtree {
+        pieces_root,
+        base_layer: (piece_length as usize / BLOCK_LENGTH).trailing_zeros(),
+        index: 0,
+        width: piece_count.next_power_of_two(),
+    };
+    subtree.request(stream, piece_count, &pieces_root).await
+}
+
+/// Hashes of one layer of a file's merkle tree that all lead up to one node above them
+struct Subtree {
+    pieces_root: Hash,
+    base_layer: u32,
+    /// Position of the first hash within its layer
+    index: u32,
+    /// Number of hashes below the node, a power of two
+    width: u32,
+}
+
+impl Subtree {
+    /// The first `count` hashes, proven against `node`. Subtrees wider than
+    /// [`MAX_HASHES_PER_REQUEST`] are asked for in parts, each with the uncle hashes
+    /// leading up to `node`.
+    async fn request(&self, stream: &mut TcpStream, count: u32, node: &Hash) -> Result<Vec<Hash>> {
+        let length = self.width.min(MAX_HASHES_PER_REQUEST);
+        let mut hashes = Vec::with_capacity(count as usize);
+        for part in 0..count.div_ceil(length) {
+            let request = HashRequest {
+                pieces_root: self.pieces_root,
+                base_layer: self.base_layer,
+                index: self.index + part * length,
+                length,
+                proof_layers: (self.width / length).trailing_zeros(),
+            };
+            hashes.extend(request_hashes(stream, request, node).await?);
+        }
+        hashes.truncate(count as usize);
+        Ok(hashes)
+    }
+}
+
+/// The `piece layers` a v2 or hybrid torrent's metainfo needs, asked of the peer for every
+/// file longer than one piece, e.g. for a torrent whose `info` came from a magnet link
+pub async fn fetch_piece_layers(
+    stream: &mut TcpStream,
+    info: &Info,
+) -> Result<BTreeMap<Hash, Vec<u8>>> {
+    let mut piece_layers = BTreeMap::new();
+    for file in info.file_tree.iter().flatten() {
+        let Some(pieces_root) = file.pieces_root else {
+            continue;
+        };
+        if file.length <= info.piece_length as u64 || piece_layers.contains_key(&pieces_root) {
+            continue;
+        }
+        let layer = request_piece_layer(stream, info, file)
+            .await
+            .with_context(|| format!("failed to get the piece layer of {}", file.path.join("/")))?;
+        piece_layers.insert(pieces_root, layer.concat());
+    }
+    Ok(piece_layers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Four leaves with distinct hashes and the two nodes above them
+    fn tree() -> ([Hash; 4], [Hash; 2]) {
+        let leaves = [[1; 32], [2; 32], [3; 32], [4; 32]];
+        let parents = [
+            merkle::hash_pair(&leaves[0], &leaves[1]),
+            merkle::hash_pair(&leaves[2], &leaves[3]),
+        ];
+        (leaves, parents)
+    }
+
+    fn request(index: u32, length: u32, proof_layers: u32) -> HashRequest {
+        HashRequest {
+            pieces_root: [9; 32],
+            base_layer: 0,
+            index,
+            length,
+            proof_layers,
+        }
+    }
+
+    /// Payload of a `hashes` message answering `request`
+    fn payload(request: HashRequest, nodes: &[Hash]) -> Vec<u8> {
+        let mut payload = request.encode(HASHES_ID)[5..].to_vec();
+        payload.extend(nodes.concat());
+        payload
+    }
+
+    #[test]
+    fn encodes_hash_requests() {
+        let request = HashRequest {
+            base_layer: 1,
+            ..request(512, 256, 3)
+        };
+        let message = request.encode(HASH_REQUEST_ID);
+        assert_eq!(message.len(), 53);
+        assert_eq!(message[..5], [0, 0, 0, 49, HASH_REQUEST_ID]);
+        assert_eq!(message[37..41], [0, 0, 0, 1]);
+        assert_eq!(HashRequest::from_payload(&message[5..]), Some(request));
+        assert_eq!(HashRequest::from_payload(&message[5..52]), None);
+    }
+
+    #[test]
+    fn splits_hashes_from_proof() {
+        let (leaves, parents) = tree();
+        let hashes = Hashes::from_payload(&payload(
+            request(2, 2, 1),
+            &[leaves[2], leaves[3], parents[0]],
+        ))
+        .unwrap();
+        assert_eq!(hashes.hashes, leaves[2..]);
+        assert_eq!(hashes.proof, [parents[0]]);
+        // fewer hashes than asked for, or a partial hash
+        assert_eq!(
+            Hashes::from_payload(&payload(request(2, 2, 1), &[leaves[2]])),
+            None
+        );
+        let mut partial = payload(request(0, 2, 0), &leaves[..2]);
+        partial.push(0);
+        assert_eq!(Hashes::from_payload(&partial), None);
+    }
+
+    #[test]
+    fn verifies_hashes_with_proof_layers() {
+        let (leaves, parents) = tree();
+        let root = merkle::hash_pair(&parents[0], &parents[1]);
+        let verify = |request: HashRequest, nodes: &[Hash]| {
+            Hashes::from_payload(&payload(request, nodes))
+                .unwrap()
+                .verify(&root)
+        };
+        assert!(verify(request(0, 4, 0), &leaves));
+        assert!(verify(
+            request(0, 2, 1),
+            &[leaves[0], leaves[1], parents[1]]
+        ));
+        assert!(verify(
+            request(2, 2, 1),
+            &[leaves[2], leaves[3], parents[0]]
+        ));
+        // a proof on the wrong side, a wrong hash and a length that is not a power of two
+        assert!(!verify(
+            request(0, 2, 1),
+            &[leaves[2], leaves[3], parents[0]]
+        ));
+        assert!(!verify(
+            request(0, 2, 1),
+            &[leaves[0], leaves[0], parents[1]]
+        ));
+        assert!(!verify(request(0, 3, 0), &leaves[..3]));
+    }
+
+    #[test]
+    fn checks_blocks_and_their_padding() {
+        let content = vec![5; 100];
+        let hashes = PieceHashes {
+            leaves: vec![merkle::hash_block(&content)],
+            file_bytes: 100,
+        };
+        let mut block = content.clone();
+        block.resize(BLOCK_LENGTH, 0);
+        assert!(hashes.check_block(0, &block));
+        assert!(hashes.check_block(1, &vec![0; BLOCK_LENGTH]));
+        block[200] = 1;
+        assert!(!hashes.check_block(0, &block));
+        assert!(!hashes.check_block(0, &[6; 100]));
+    }
+}
@@ -1,7 +1,20 @@
 pub mod bencode_parser;
+pub mod create;
+pub mod edit;
+pub mod hashes;
+pub mod magnet;
+pub mod merkle;
+pub mod metadata;
 pub mod peer;
+pub mod report;
+pub mod sanitize;
+pub mod storage;
+#[cfg(test)]
+mod test_util;
 pub mod torrent;
 pub mod tracker;
+pub mod verify;
+pub mod webseed;
 
 pub const MAX_CONCURRENT_REQUESTS: usize = 5;
 pub const DEFAULT_BLOCK_LENGTH: u32 = 16 * 1024;
@@ -0,0 +1,326 @@
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+
+use base32::Alphabet;
+use urlencoding::encode;
+
+use crate::{
+    merkle::Hash,
+    torrent::{MetaVersion, Torrent},
+};
+
+const PREFIX: &str = "magnet:?";
+const BTIH_URN: &str = "urn:btih:";
+/// v2 info hash as a SHA-256 multihash, `0x12` for the function and `0x20` for the length
+const BTMH_URN: &str = "urn:btmh:1220";
+
+/// A `magnet:` link, which names a torrent by its info hash instead of carrying the metainfo
+/// [spec](http://bittorrent.org/beps/bep_0009.html#magnet-uri-format)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Magnet {
+    /// `xt=urn:btih:`, written as 40 hex or 32 base32 characters, absent for v2-only torrents
+    pub info_hash: Option<[u8; 20]>,
+    /// `xt=urn:btmh:`, the v2 info hash of v2 and hybrid torrents
+    pub info_hash_v2: Option<Hash>,
+    /// `dn`, suggested name to show before the metadata is known
+    pub display_name: Option<String>,
+    /// `tr`, tracker URLs in the order given
+    pub trackers: Vec<String>,
+    /// `x.pe`, peers to connect to directly as `host:port`
+    pub peers: Vec<String>,
+    /// `ws`, web seed URLs
+    /// [spec](http://bittorrent.org/beps/bep_0019.html)
+    pub web_seeds: Vec<String>,
+    /// `so`, indexes of the files to download, e.g. `0,2,4-6`
+    /// [spec](http://bittorrent.org/beps/bep_0053.html)
+    pub select_only: Vec<RangeInclusive<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MagnetError {
+    /// The link does not start with `magnet:?`
+    NotAMagnet,
+    /// There is no `xt=urn:btih:` or `xt=urn:btmh:` parameter
+    MissingInfoHash,
+    /// The `btih` is neither 40 hex nor 32 base32 characters, or the `btmh` is not a SHA-256
+    /// multihash
+    InvalidInfoHash(String),
+    /// A parameter value is not valid percent-encoded UTF-8
+    InvalidEncoding { key: String },
+    /// `so` is not a list of indexes and ranges
+    InvalidSelectOnly(String),
+}
+
+impl fmt::Display for MagnetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MagnetError::NotAMagnet => write!(f, "magnet link should start with `{PREFIX}`"),
+            MagnetError::MissingInfoHash => write!(
+                f,
+                "magnet link has no `xt={BTIH_URN}` or `xt=urn:btmh:` parameter"
+            ),
+            MagnetError::InvalidInfoHash(hash) => write!(f, "invalid info hash `{hash}`"),
+            MagnetError::InvalidEncoding { key } => {
+                write!(f, "`{key}` is not valid percent-encoded UTF-8")
+            }
+            MagnetError::InvalidSelectOnly(so) => write!(f, "invalid `so` value `{so}`"),
+        }
+    }
+}
+
+impl std::error::Error for MagnetError {}
+
+impl Magnet {
+    /// Magnet link for a loaded torrent, with its name and every tracker it announces to
+    pub fn from_torrent(torrent: &Torrent) -> Magnet {
+        let mut trackers: Vec<String> = torrent.announce.iter().cloned().collect();
+        for tracker in torrent.trackers().into_iter().flatten() {
+            if !trackers.contains(&tracker) {
+                trackers.push(tracker);
+            }
+        }
+        let info_hash_v2 = torrent.info_hash_v2();
+        Magnet {
+            // v2-only torrents have no v1 hash, only the truncated v2 one
+            info_hash: (torrent.info.version() != MetaVersion::V2).then(|| torrent.info_hash()),
+            info_hash_v2,
+            display_name: Some(torrent.info.name.clone()),
+            trackers,
+            peers: vec![],
+            web_seeds: torrent.url_list.clone(),
+            select_only: vec![],
+        }
+    }
+    /// [`Torrent::swarm_hash`] of the torrent the link points to
+    pub fn swarm_hash(&self) -> [u8; 20] {
+        match (self.info_hash, self.info_hash_v2) {
+            (Some(info_hash), _) => info_hash,
+            (None, Some(info_hash_v2)) => info_hash_v2[..20].try_into().unwrap(),
+            (None, None) => unreachable!("parsed magnets have at least one info hash"),
+        }
+    }
+}
+
+impl FromStr for Magnet {
+    type Err = MagnetError;
+
+    fn from_str(link: &str) -> Result<Self, Self::Err> {
+        let query = link.strip_prefix(PREFIX).ok_or(MagnetError::NotAMagnet)?;
+        let mut magnet = Magnet {
+            info_hash: None,
+            info_hash_v2: None,
+            display_name: None,
+            trackers: vec![],
+            peers: vec![],
+            web_seeds: vec![],
+            select_only: vec![],
+        };
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            let value = decode_value(key, value)?;
+            match key {
+                "xt" => {
+                    if let Some(hash) = value.strip_prefix(BTIH_URN) {
+                        if magnet.info_hash.is_none() {
+                            magnet.info_hash = Some(parse_info_hash(hash)?);
+                        }
+                    } else if let Some(hash) = value.strip_prefix("urn:btmh:") {
+                        if magnet.info_hash_v2.is_none() {
+                            magnet.info_hash_v2 = Some(parse_info_hash_v2(hash)?);
+                        }
+                    }
+                }
+                "dn" => magnet.display_name = Some(value),
+                "tr" => magnet.trackers.push(value),
+                "x.pe" => magnet.peers.push(value),
+                "ws" => magnet.web_seeds.push(value),
+                "so" => magnet.select_only.extend(parse_select_only(&value)?),
+                _ => {}
+            }
+        }
+        if magnet.info_hash.is_none() && magnet.info_hash_v2.is_none() {
+            return Err(MagnetError::MissingInfoHash);
+        }
+        Ok(magnet)
+    }
+}
+
+impl fmt::Display for Magnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut hashes = vec![];
+        if let Some(info_hash) = self.info_hash {
+            hashes.push(format!("xt={BTIH_URN}{}", hex::encode(info_hash)));
+        }
+        if let Some(info_hash_v2) = self.info_hash_v2 {
+            hashes.push(format!("xt={BTMH_URN}{}", hex::encode(info_hash_v2)));
+        }
+        write!(f, "{PREFIX}{}", hashes.join("&"))?;
+        if let Some(name) = &self.display_name {
+            write!(f, "&dn={}", encode(name))?;
+        }
+        for tracker in &self.trackers {
+            write!(f, "&tr={}", encode(tracker))?;
+        }
+        for peer in &self.peers {
+            write!(f, "&x.pe={}", encode(peer))?;
+        }
+        for web_seed in &self.web_seeds {
+            write!(f, "&ws={}", encode(web_seed))?;
+        }
+        if !self.select_only.is_empty() {
+            let ranges: Vec<String> = self
+                .select_only
+                .iter()
+                .map(|range| {
+                    if range.start() == range.end() {
+                        range.start().to_string()
+                    } else {
+                        format!("{}-{}", range.start(), range.end())
+                    }
+                })
+                .collect();
+            write!(f, "&so={}", ranges.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Percent-decodes a parameter value, `+` standing for a space as in HTML forms
+fn decode_value(key: &str, value: &str) -> Result<String, MagnetError> {
+    urlencoding::decode(&value.replace('+', " "))
+        .map(|value| value.into_owned())
+        .map_err(|_| MagnetError::InvalidEncoding {
+            key: key.to_string(),
+        })
+}
+
+fn parse_info_hash(hash: &str) -> Result<[u8; 20], MagnetError> {
+    let bytes = match hash.len() {
+        40 => hex::decode(hash).ok(),
+        32 => base32::decode(
+            Alphabet::Rfc4648 { padding: false },
+            &hash.to_ascii_uppercase(),
+        ),
+        _ => None,
+    };
+    bytes
+        .and_then(|bytes| <[u8; 20]>::try_from(bytes).ok())
+        .ok_or_else(|| MagnetError::InvalidInfoHash(hash.to_string()))
+}
+
+/// SHA-256 multihash, the only kind v2 torrents use
+fn parse_info_hash_v2(multihash: &str) -> Result<Hash, MagnetError> {
+    multihash
+        .strip_prefix("1220")
+        .and_then(|hash| hex::decode(hash).ok())
+        .and_then(|bytes| Hash::try_from(bytes).ok())
+        .ok_or_else(|| MagnetError::InvalidInfoHash(multihash.to_string()))
+}
+
+/// `0,2,4-6` into `[0..=0, 2..=2, 4..=6]`
+fn parse_select_only(so: &str) -> Result<Vec<RangeInclusive<usize>>, MagnetError> {
+    let invalid = || MagnetError::InvalidSelectOnly(so.to_string());
+    so.split(',')
+        .map(|item| {
+            let (start, end) = item.split_once('-').unwrap_or((item, item));
+            let start: usize = start.parse().map_err(|_| invalid())?;
+            let end: usize = end.parse().map_err(|_| invalid())?;
+            if start > end {
+                return Err(invalid());
+            }
+            Ok(start..=end)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "0123456789abcdef0123456789abcdef01234567";
+    const BASE32: &str = "aerukz4jvpg66ajdivtytk6n54asgrlh";
+
+    fn info_hash() -> [u8; 20] {
+        hex::decode(HEX).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn parses_hex_and_base32_info_hashes() {
+        let hex: Magnet = format!("magnet:?xt=urn:btih:{HEX}").parse().unwrap();
+        let base32: Magnet = format!("magnet:?xt=urn:btih:{}", BASE32.to_uppercase())
+            .parse()
+            .unwrap();
+        assert_eq!(hex.info_hash, Some(info_hash()));
+        assert_eq!(base32, hex);
+        assert_eq!(hex.swarm_hash(), info_hash());
+    }
+
+    #[test]
+    fn parses_v2_multihashes() {
+        let hash_v2 = [7; 32];
+        let link = format!("magnet:?xt=urn:btmh:1220{}", hex::encode(hash_v2));
+        let magnet: Magnet = link.parse().unwrap();
+        assert_eq!(magnet.info_hash, None);
+        assert_eq!(magnet.info_hash_v2, Some(hash_v2));
+        assert_eq!(magnet.swarm_hash(), [7; 20]);
+        // SHA-1 multihashes are not v2 info hashes
+        let link = format!("magnet:?xt=urn:btmh:1114{HEX}");
+        assert!(matches!(
+            link.parse::<Magnet>(),
+            Err(MagnetError::InvalidInfoHash(_))
+        ));
+    }
+
+    #[test]
+    fn parses_every_parameter() {
+        let link = format!(
+            "magnet:?xt=urn:btih:{HEX}&dn=a+b%2Fc&tr=http%3A%2F%2Ft1%2Fa&tr=udp://t2:80\
+             &x.pe=1.2.3.4:5&ws=http://ws/&so=0,2,4-6&unknown=1"
+        );
+        let magnet: Magnet = link.parse().unwrap();
+        assert_eq!(magnet.display_name.as_deref(), Some("a b/c"));
+        assert_eq!(magnet.trackers, ["http://t1/a", "udp://t2:80"]);
+        assert_eq!(magnet.peers, ["1.2.3.4:5"]);
+        assert_eq!(magnet.web_seeds, ["http://ws/"]);
+        assert_eq!(magnet.select_only, [0..=0, 2..=2, 4..=6]);
+    }
+
+    #[test]
+    fn rejects_invalid_links() {
+        let parse = |link: &str| link.parse::<Magnet>().unwrap_err();
+        assert_eq!(parse("http://example.com"), MagnetError::NotAMagnet);
+        assert_eq!(parse("magnet:?dn=a"), MagnetError::MissingInfoHash);
+        assert_eq!(
+            parse("magnet:?xt=urn:btih:0123"),
+            MagnetError::InvalidInfoHash("0123".to_string())
+        );
+        assert_eq!(
+            parse(&format!("magnet:?xt=urn:btih:{HEX}&dn=%FF")),
+            MagnetError::InvalidEncoding {
+                key: "dn".to_string()
+            }
+        );
+        for so in ["1-", "a", "3-1", "1,,2"] {
+            assert_eq!(
+                parse(&format!("magnet:?xt=urn:btih:{HEX}&so={so}")),
+                MagnetError::InvalidSelectOnly(so.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let magnet = Magnet {
+            info_hash: Some(info_hash()),
+            info_hash_v2: Some([9; 32]),
+            display_name: Some("a b&c".to_string()),
+            trackers: vec!["http://t/announce?x=1&y=2".to_string()],
+            peers: vec!["[::1]:6881".to_string()],
+            web_seeds: vec!["http://ws/a b".to_string()],
+            select_only: vec![0..=0, 3..=5],
+        };
+        let link = magnet.to_string();
+        assert!(link.starts_with(&format!("magnet:?xt=urn:btih:{HEX}&xt=urn:btmh:1220")));
+        assert!(link.ends_with("&so=0,3-5"));
+        assert_eq!(link.parse::<Magnet>().unwrap(), magnet);
+    }
+}
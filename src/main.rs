@@ -1,13 +1,33 @@
-use anyhow::Result;
-use bittorrust::{peer::Peer, torrent::Torrent, tracker::TrackerRequest};
-use clap::{command, Parser, Subcommand};
+use anyhow::{bail, Context, Result};
+use bittorrust::{
+    bencode_parser::{self, BinaryEncoding, Query, Value},
+    create::TorrentBuilder,
+    edit::TorrentEditor,
+    hashes,
+    magnet::Magnet,
+    metadata,
+    peer::{Peer, Pieces},
+    report::TorrentReport,
+    torrent::{MetaVersion, Torrent},
+    tracker::TrackerRequest,
+    verify,
+    webseed::WebSeed,
+    DEFAULT_BLOCK_LENGTH,
+};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::{
     collections::HashSet,
-    net::{Ipv4Addr, SocketAddrV4},
-    path::PathBuf,
-    sync::Arc,
+    net::{SocketAddr, SocketAddrV4},
+    path::{Path, PathBuf},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
 };
-use tokio::{stream, sync::Mutex};
+
+/// Bytes left announced for a magnet link: the size is unknown without the metadata, so a
+/// block is claimed to be treated as a leecher
+const MAGNET_LEFT: u64 = DEFAULT_BLOCK_LENGTH as u64;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -20,18 +40,50 @@ struct Args {
 #[clap(rename_all = "snake_case")]
 enum Command {
     Decode {
-        value: String,
+        /// Bencoded value, read from `--input` or stdin when omitted
+        value: Option<String>,
+        /// File to read the bencoded value from, `-` for stdin
+        #[arg(short, long)]
+        input: Option<PathBuf>,
+        #[arg(short, long, value_enum, default_value_t = DecodeFormat::Json)]
+        format: DecodeFormat,
+        /// How byte strings that are not UTF-8 are shown
+        #[arg(long, value_enum, default_value_t = BinaryFormat::Hex)]
+        binary: BinaryFormat,
+        /// Only print the nodes selected by this path, e.g. `info.files[*].path`
+        #[arg(long)]
+        path: Option<Query>,
     },
     Info {
         torrent: PathBuf,
+        /// Reject the torrent if it is not canonical bencode
+        #[arg(long)]
+        lint: bool,
+        /// Print the metainfo nodes selected by this path as JSON instead of the summary
+        #[arg(long)]
+        path: Option<Query>,
+        /// Print the summary as JSON
+        #[arg(long, conflicts_with = "path")]
+        json: bool,
     },
     Peers {
-        torrent: PathBuf,
+        /// `.torrent` file or magnet link
+        torrent: String,
     },
     Handshake {
-        torrent: PathBuf,
+        /// `.torrent` file or magnet link
+        torrent: String,
         // peer: String,
     },
+    /// Print a magnet link for a `.torrent` file
+    Magnet { torrent: PathBuf },
+    /// Fetch the metadata of a magnet link from peers and save it as a `.torrent` file
+    #[command(name = "magnet2torrent")]
+    Magnet2Torrent {
+        #[arg(short)]
+        output: PathBuf,
+        magnet: Magnet,
+    },
     DownloadPiece {
         #[arg(short)]
         output: PathBuf,
@@ -42,7 +94,142 @@ enum Command {
         #[arg(short)]
         output: PathBuf,
         torrent: PathBuf,
+        /// Then check the files against the torrent's `md5sum` and `sha1`
+        #[arg(long)]
+        checksums: bool,
     },
+    /// Check downloaded data against the torrent's piece hashes
+    Verify {
+        /// Where the torrent was downloaded to, as given to `download`
+        #[arg(short)]
+        output: PathBuf,
+        torrent: PathBuf,
+        /// Download the pieces that are missing or corrupt
+        #[arg(long)]
+        repair: bool,
+        /// Also check the files against the torrent's `md5sum` and `sha1`
+        #[arg(long)]
+        checksums: bool,
+    },
+    /// Create a `.torrent` file for a file or directory
+    #[command(rename_all = "kebab-case")]
+    Create {
+        #[arg(short)]
+        output: PathBuf,
+        /// File or directory to share
+        content: PathBuf,
+        /// Tracker URL, repeat for more tiers, comma-separate trackers sharing a tier, omit
+        /// for a trackerless torrent
+        #[arg(short, long)]
+        announce: Vec<String>,
+        /// Piece length in bytes, picked from the content size when omitted
+        #[arg(long)]
+        piece_length: Option<u32>,
+        #[arg(long)]
+        comment: Option<String>,
+        /// Creator name, this program's name when omitted
+        #[arg(long)]
+        created_by: Option<String>,
+        /// Leave out the creation date
+        #[arg(long)]
+        no_date: bool,
+        /// Only allow peers from the trackers
+        #[arg(long)]
+        private: bool,
+        /// Source tag, e.g. the tracker's name, stored in the info dict
+        #[arg(long)]
+        source: Option<String>,
+        /// Web seed URL, can be repeated
+        #[arg(short, long)]
+        web_seed: Vec<String>,
+        /// Skip files and directories matching this pattern, e.g. `*.tmp`, can be repeated
+        #[arg(short = 'x', long)]
+        ignore: Vec<String>,
+        /// Hash schemes to include
+        #[arg(long, value_enum, default_value_t = TorrentVersion::V1)]
+        meta_version: TorrentVersion,
+        /// Store the MD5 and SHA-1 of every file
+        #[arg(long)]
+        checksums: bool,
+    },
+    /// Change the trackers, comment or web seeds of a `.torrent` file, keeping its info hash
+    #[command(rename_all = "kebab-case")]
+    Edit {
+        torrent: PathBuf,
+        /// Where to write the edited torrent, over the input when omitted
+        #[arg(short)]
+        output: Option<PathBuf>,
+        /// Replace the trackers, repeat for more tiers, comma-separate trackers sharing a tier
+        #[arg(short, long)]
+        announce: Vec<String>,
+        /// Add a tier of comma-separated trackers after the existing ones, can be repeated
+        #[arg(long)]
+        add_tracker: Vec<String>,
+        /// Remove a tracker from every tier, can be repeated
+        #[arg(long)]
+        remove_tracker: Vec<String>,
+        #[arg(long, conflicts_with = "no_comment")]
+        comment: Option<String>,
+        /// Remove the comment
+        #[arg(long)]
+        no_comment: bool,
+        #[arg(long)]
+        created_by: Option<String>,
+        /// Remove every web seed before adding the `--web-seed` ones
+        #[arg(long)]
+        no_web_seeds: bool,
+        /// Add a web seed URL, can be repeated
+        #[arg(short, long)]
+        web_seed: Vec<String>,
+        /// Remove a web seed URL, can be repeated
+        #[arg(long)]
+        remove_web_seed: Vec<String>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DecodeFormat {
+    /// JSON, binary byte strings as `{"hex": ...}` or `{"base64": ...}`
+    Json,
+    /// Indented tree
+    Tree,
+    /// Canonical bencode, re-encoded from the decoded value
+    Raw,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum BinaryFormat {
+    Hex,
+    Base64,
+}
+
+impl From<BinaryFormat> for BinaryEncoding {
+    fn from(format: BinaryFormat) -> Self {
+        match format {
+            BinaryFormat::Hex => BinaryEncoding::Hex,
+            BinaryFormat::Base64 => BinaryEncoding::Base64,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum TorrentVersion {
+    /// SHA-1 pieces, readable by every client
+    V1,
+    /// SHA-256 merkle trees per file (BEP 52)
+    V2,
+    /// Both, for a swarm shared by v1 and v2 clients
+    Hybrid,
+}
+
+impl From<TorrentVersion> for MetaVersion {
+    fn from(version: TorrentVersion) -> Self {
+        match version {
+            TorrentVersion::V1 => MetaVersion::V1,
+            TorrentVersion::V2 => MetaVersion::V2,
+            TorrentVersion::Hybrid => MetaVersion::Hybrid,
+        }
+    }
 }
 
 #[tokio::main]
@@ -50,82 +237,484 @@ async fn main() -> Result<()> {
     let args = Args::parse();
 
     match args.command {
-        Command::Decode { value } => {
-            let (decoded, _) = bittorrust::bencode_parser::decode(&value);
-            println!("{}", decoded);
-        }
-        Command::Info { torrent } => {
-            let decoded_torrent = Torrent::new(torrent).await;
-            println!("Tracker URL: {}", decoded_torrent.announce);
-            println!("Length: {}", decoded_torrent.info.length.unwrap());
-            let info_hash = decoded_torrent.info_hash();
-            println!("Info Hash: {}", hex::encode(info_hash));
-            println!("Piece Length: {}", decoded_torrent.info.piece_length);
-            println!("Piece Hashes: {:?}", decoded_torrent.get_piece_hashes());
+        Command::Decode {
+            value,
+            input,
+            format,
+            binary,
+            path,
+        } => {
+            let bytes = match (value, input) {
+                (Some(value), _) => value.into_bytes(),
+                (None, Some(path)) if path != Path::new("-") => tokio::fs::read(&path)
+                    .await
+                    .with_context(|| format!("failed to read {}", path.display()))?,
+                (None, _) => {
+                    let mut bytes = Vec::new();
+                    tokio::io::stdin().read_to_end(&mut bytes).await?;
+                    bytes
+                }
+            };
+            let (decoded, rest) =
+                bencode_parser::decode_bytes(&bytes).context("invalid bencode")?;
+            if !rest.is_empty() {
+                eprintln!("ignoring {} bytes after the value", rest.len());
+            }
+            let selected = match &path {
+                Some(path) => path.select(&decoded),
+                None => vec![&decoded],
+            };
+            for value in selected {
+                print_value(value, format, binary.into()).await?;
+            }
+        }
+        Command::Info {
+            torrent,
+            lint,
+            path,
+            json,
+        } => {
+            if lint {
+                lint_torrent(&torrent).await?;
+            }
+            if let Some(path) = path {
+                let bytes = tokio::fs::read(&torrent)
+                    .await
+                    .with_context(|| format!("failed to read {}", torrent.display()))?;
+                let (decoded, _) =
+                    bencode_parser::decode_bytes(&bytes).context("invalid metainfo")?;
+                for value in path.select(&decoded) {
+                    print_value(value, DecodeFormat::Json, BinaryEncoding::Hex).await?;
+                }
+                return Ok(());
+            }
+            let report = TorrentReport::new(&Torrent::new(torrent).await?);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{report}");
+            }
         }
         Command::Peers { torrent } => {
-            let decoded_torrent = Torrent::new(torrent).await;
-            let info_hash = decoded_torrent.info_hash();
-            let info_hash_url = TrackerRequest::url_encode(info_hash);
-            let req = TrackerRequest::new(&decoded_torrent, info_hash);
-            let tracker_response =
-                TrackerRequest::request(&req, info_hash_url, &decoded_torrent.announce).await;
-            let peers = tracker_response.get_peers();
+            let (_, peers) = find_peers(&torrent).await?;
             println!("peers: {:?}", peers);
         }
         Command::Handshake { torrent } => {
             //TODO: get peer socket from args
             // $ ./your_bittorrent.sh handshake sample.torrent <peer_ip>:<peer_port>
-            let decoded_torrent = Torrent::new(torrent).await;
-            let info_hash = decoded_torrent.info_hash();
-            let info_hash_url = TrackerRequest::url_encode(info_hash);
-            let req = TrackerRequest::new(&decoded_torrent, info_hash);
-            let tracker_response =
-                TrackerRequest::request(&req, info_hash_url, &decoded_torrent.announce).await;
-            let peers = tracker_response.get_peers();
-            let peer = Peer { socket: peers[0] };
+            let (info_hash, peers) = find_peers(&torrent).await?;
+            let peer = Peer {
+                socket: *peers.first().context("the tracker gave no peers")?,
+            };
             let _ = Peer::handshake(peer, info_hash).await;
         }
+        Command::Magnet { torrent } => {
+            let decoded_torrent = Torrent::new(torrent).await?;
+            println!("{}", Magnet::from_torrent(&decoded_torrent));
+        }
+        Command::Magnet2Torrent { output, magnet } => {
+            let mut info = None;
+            let mut fetched = None;
+            // any peer with the extensions will do, move on to the next one when a peer fails
+            for socket in magnet_peers(&magnet).await? {
+                match fetch_torrent(socket, &magnet, &mut info).await {
+                    Ok(torrent) => {
+                        fetched = Some(torrent);
+                        break;
+                    }
+                    Err(err) => eprintln!("{socket}: {err:#}"),
+                }
+            }
+            let mut decoded_torrent = fetched.with_context(|| match info {
+                None => "no peer could provide the metadata",
+                Some(_) => "no peer could provide the piece layers",
+            })?;
+            decoded_torrent.url_list = magnet.web_seeds.clone();
+            tokio::fs::write(&output, decoded_torrent.to_bytes())
+                .await
+                .with_context(|| format!("failed to write {}", output.display()))?;
+            println!(
+                "Saved {} to {}",
+                decoded_torrent.info.name,
+                output.display()
+            );
+        }
         Command::DownloadPiece {
             output,
             torrent,
             piece,
         } => {
-            let decoded_torrent = Torrent::new(torrent).await;
-            let info_hash = decoded_torrent.info_hash();
-            let info_hash_url = TrackerRequest::url_encode(info_hash);
+            let decoded_torrent = Torrent::new(torrent).await?;
             let piece_hashes = decoded_torrent.get_piece_hashes();
-            let req = TrackerRequest::new(&decoded_torrent, info_hash);
-            let tracker_response =
-                TrackerRequest::request(&req, info_hash_url, &decoded_torrent.announce).await;
-            let peers = tracker_response.get_peers();
-            let peer = Peer { socket: peers[0] };
-            let stream = Arc::new(Mutex::new(Peer::handshake(peer, info_hash).await));
-            let pending_tasks: Arc<Mutex<HashSet<u64>>> = Arc::new(Mutex::new(HashSet::new()));
-            Peer::download_piece(
+            let web_seeds = WebSeed::from_torrent(&decoded_torrent);
+            let (stream, verify_blocks) = connect_peer(&decoded_torrent, &web_seeds).await?;
+            Peer::download_torrent(
                 stream,
-                piece,
-                decoded_torrent.info.piece_length,
-                pending_tasks,
-                piece_hashes.get(piece as usize).unwrap().to_string(),
-                None,
+                decoded_torrent,
+                piece_hashes,
                 output,
+                Pieces::Single(piece),
+                verify_blocks,
+                web_seeds,
             )
-            .await;
+            .await?;
         }
-        Command::Download { output, torrent } => {
-            let decoded_torrent = Torrent::new(torrent).await;
-            let info_hash = decoded_torrent.info_hash();
+        Command::Download {
+            output,
+            torrent,
+            checksums,
+        } => {
+            let decoded_torrent = Torrent::new(torrent).await?;
             let piece_hashes = decoded_torrent.get_piece_hashes();
-            let req = TrackerRequest::new(&decoded_torrent, info_hash);
-            let info_hash_url = TrackerRequest::url_encode(info_hash);
-            let tracker_response =
-                TrackerRequest::request(&req, info_hash_url, &decoded_torrent.announce).await;
-            let peers = tracker_response.get_peers();
-            let peer = Peer { socket: peers[0] };
-            let stream = Peer::handshake(peer, info_hash).await;
-            Peer::download_torrent(stream, decoded_torrent, piece_hashes, output, None).await;
+            let web_seeds = WebSeed::from_torrent(&decoded_torrent);
+            let (stream, verify_blocks) = connect_peer(&decoded_torrent, &web_seeds).await?;
+            Peer::download_torrent(
+                stream,
+                decoded_torrent.clone(),
+                piece_hashes,
+                output.clone(),
+                Pieces::All,
+                verify_blocks,
+                web_seeds,
+            )
+            .await?;
+            if checksums {
+                check_file_checksums(&decoded_torrent, &output)?;
+            }
+        }
+        Command::Verify {
+            output,
+            torrent,
+            repair,
+            checksums,
+        } => {
+            let decoded_torrent = Torrent::new(torrent).await?;
+            let report = verify::verify(&decoded_torrent, &output)?;
+            print!("{report}");
+            let failed = report.failed_pieces();
+            if repair && !report.is_complete() {
+                let piece_hashes = decoded_torrent.get_piece_hashes();
+                let web_seeds = WebSeed::from_torrent(&decoded_torrent);
+                let (stream, verify_blocks) = connect_peer(&decoded_torrent, &web_seeds).await?;
+                println!("repairing {} pieces", failed.len());
+                Peer::download_torrent(
+                    stream,
+                    decoded_torrent.clone(),
+                    piece_hashes,
+                    output.clone(),
+                    Pieces::Listed(failed),
+                    verify_blocks,
+                    web_seeds,
+                )
+                .await?;
+                let report = verify::verify(&decoded_torrent, &output)?;
+                print!("{report}");
+                if !report.is_complete() {
+                    bail!("the data is still incomplete after the repair");
+                }
+            } else if !report.is_complete() {
+                bail!("the downloaded data is incomplete");
+            }
+            if checksums {
+                check_file_checksums(&decoded_torrent, &output)?;
+            }
+        }
+        Command::Create {
+            output,
+            content,
+            announce,
+            piece_length,
+            comment,
+            created_by,
+            no_date,
+            private,
+            source,
+            web_seed,
+            ignore,
+            meta_version,
+            checksums,
+        } => {
+            let mut builder = TorrentBuilder::new(content)
+                .private(private)
+                .meta_version(meta_version.into())
+                .checksums(checksums);
+            for tier in announce {
+                builder = builder.announce_tier(tier.split(',').map(str::to_string).collect());
+            }
+            if let Some(piece_length) = piece_length {
+                builder = builder.piece_length(piece_length);
+            }
+            if let Some(comment) = comment {
+                builder = builder.comment(comment);
+            }
+            if created_by.is_some() {
+                builder = builder.created_by(created_by);
+            }
+            if no_date {
+                builder = builder.creation_date(None);
+            }
+            if let Some(source) = source {
+                builder = builder.source(source);
+            }
+            for url in web_seed {
+                builder = builder.web_seed(url);
+            }
+            for pattern in ignore {
+                builder = builder.ignore(pattern);
+            }
+            let metainfo = builder.build()?;
+            let created = Torrent::from_bytes(&metainfo)?;
+            tokio::fs::write(&output, &metainfo)
+                .await
+                .with_context(|| format!("failed to write {}", output.display()))?;
+            if created.info.version() != MetaVersion::V2 {
+                println!("Info Hash: {}", hex::encode(created.info_hash()));
+            }
+            if let Some(info_hash_v2) = created.info_hash_v2() {
+                println!("Info Hash v2: {}", hex::encode(info_hash_v2));
+            }
+            println!(
+                "Pieces: {} of {} bytes",
+                created.info.piece_count(),
+                created.info.piece_length
+            );
+        }
+        Command::Edit {
+            torrent,
+            output,
+            announce,
+            add_tracker,
+            remove_tracker,
+            comment,
+            no_comment,
+            created_by,
+            no_web_seeds,
+            web_seed,
+            remove_web_seed,
+        } => {
+            let bytes = tokio::fs::read(&torrent)
+                .await
+                .with_context(|| format!("failed to read {}", torrent.display()))?;
+            let mut editor = TorrentEditor::from_bytes(&bytes)?;
+            let tiers = |tiers: Vec<String>| -> Vec<Vec<String>> {
+                tiers
+                    .iter()
+                    .map(|tier| tier.split(',').map(str::to_string).collect())
+                    .collect()
+            };
+            if !announce.is_empty() {
+                editor.set_trackers(tiers(announce));
+            }
+            for tier in tiers(add_tracker) {
+                editor.add_tracker_tier(tier);
+            }
+            for url in &remove_tracker {
+                editor.remove_tracker(url);
+            }
+            if comment.is_some() || no_comment {
+                editor.comment(comment);
+            }
+            if created_by.is_some() {
+                editor.created_by(created_by);
+            }
+            if no_web_seeds {
+                editor.set_web_seeds(vec![]);
+            }
+            for url in web_seed {
+                editor.add_web_seed(url);
+            }
+            for url in &remove_web_seed {
+                editor.remove_web_seed(url);
+            }
+            let edited = editor.to_bytes()?;
+            let output = output.unwrap_or(torrent);
+            tokio::fs::write(&output, &edited)
+                .await
+                .with_context(|| format!("failed to write {}", output.display()))?;
+            let edited = Torrent::from_bytes(&edited)?;
+            println!("Saved {}", output.display());
+            if edited.info.version() != MetaVersion::V2 {
+                println!("Info Hash: {}", hex::encode(edited.info_hash()));
+            }
+            if let Some(info_hash_v2) = edited.info_hash_v2() {
+                println!("Info Hash v2: {}", hex::encode(info_hash_v2));
+            }
+        }
+    };
+    Ok(())
+}
+
+/// Prints how every file compares with its checksums, failing on a mismatch
+fn check_file_checksums(torrent: &Torrent, output: &Path) -> Result<()> {
+    let checks = verify::check_checksums(torrent, output)?;
+    if checks.is_empty() {
+        println!("the torrent has no file checksums");
+    }
+    for check in &checks {
+        println!("{check}");
+    }
+    let failed = checks.iter().filter(|check| !check.matches()).count();
+    if failed > 0 {
+        bail!("{failed} file checksums do not match");
+    }
+    Ok(())
+}
+
+async fn print_value(value: &Value, format: DecodeFormat, binary: BinaryEncoding) -> Result<()> {
+    match format {
+        DecodeFormat::Json => println!("{}", value.to_json(binary)),
+        DecodeFormat::Tree => print!("{}", value.to_tree(binary)),
+        DecodeFormat::Raw => {
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(&value.encode()).await?;
+            stdout.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Connects to the first peer the tracker knows, torrents with web seeds go on without a peer
+/// when the tracker fails or knows none
+async fn connect_peer(
+    torrent: &Torrent,
+    web_seeds: &[WebSeed],
+) -> Result<(Option<TcpStream>, bool)> {
+    let info_hash = torrent.swarm_hash();
+    let req = TrackerRequest::new(info_hash, torrent.info.total_length());
+    let info_hash_url = TrackerRequest::url_encode(info_hash);
+    let Some(tracker) = torrent.tracker() else {
+        if web_seeds.is_empty() {
+            bail!("the torrent has no tracker to find peers with");
+        }
+        println!("downloading from web seeds only");
+        return Ok((None, false));
+    };
+    let peers = match TrackerRequest::request(&req, info_hash_url, tracker).await {
+        Ok(tracker_response) => tracker_response.get_peers(),
+        Err(err) if !web_seeds.is_empty() => {
+            eprintln!("tracker: {err:#}");
+            vec![]
         }
+        Err(err) => return Err(err),
     };
+    let Some(&socket) = peers.first() else {
+        if web_seeds.is_empty() {
+            bail!("the tracker returned no peers");
+        }
+        println!("downloading from web seeds only");
+        return Ok((None, false));
+    };
+    let (stream, verify_blocks) = download_handshake(Peer { socket }, torrent).await?;
+    Ok((Some(stream), verify_blocks))
+}
+
+/// Connects to `peer` for downloading, returning whether blocks can be checked against the
+/// v2 merkle trees, which needs a v2 or hybrid torrent and a peer answering hash requests
+async fn download_handshake(peer: Peer, torrent: &Torrent) -> Result<(TcpStream, bool)> {
+    let info_hash = torrent.swarm_hash();
+    match torrent.info.version() {
+        MetaVersion::V1 => Ok((Peer::handshake(peer, info_hash).await, false)),
+        MetaVersion::V2 => {
+            let (stream, v2) = Peer::handshake_v2(peer, info_hash).await?;
+            if !v2 {
+                bail!("{} does not support BitTorrent v2", peer.socket);
+            }
+            Ok((stream, true))
+        }
+        MetaVersion::Hybrid => Peer::handshake_v2(peer, info_hash).await,
+    }
+}
+
+/// Prints every non-canonical encoding in the metainfo file and fails if there are any
+async fn lint_torrent(torrent: &PathBuf) -> Result<()> {
+    let bytes = tokio::fs::read(torrent)
+        .await
+        .with_context(|| format!("failed to read {}", torrent.display()))?;
+    let violations = bencode_parser::lint(&bytes).context("invalid metainfo")?;
+    let info_span = bencode_parser::dict_value_span(&bytes, b"info")?;
+    for violation in &violations {
+        let in_info = info_span
+            .as_ref()
+            .is_some_and(|span| span.contains(&violation.offset));
+        if in_info {
+            println!("{violation} (inside info, changes the info hash)");
+        } else {
+            println!("{violation}");
+        }
+    }
+    if !violations.is_empty() {
+        bail!("{} non-canonical encoding(s) found", violations.len());
+    }
     Ok(())
 }
+
+/// Swarm hash and peers for a `.torrent` file or a magnet link, found the way
+/// `magnet2torrent` finds them for magnet links
+async fn find_peers(torrent: &str) -> Result<([u8; 20], Vec<SocketAddrV4>)> {
+    if torrent.starts_with("magnet:") {
+        let magnet: Magnet = torrent.parse()?;
+        return Ok((magnet.swarm_hash(), magnet_peers(&magnet).await?));
+    }
+    let decoded_torrent = Torrent::new(PathBuf::from(torrent)).await?;
+    let tracker = decoded_torrent
+        .tracker()
+        .context("the torrent has no tracker to find peers with")?;
+    let info_hash = decoded_torrent.swarm_hash();
+    let info_hash_url = TrackerRequest::url_encode(info_hash);
+    let req = TrackerRequest::new(info_hash, decoded_torrent.info.total_length());
+    let tracker_response = TrackerRequest::request(&req, info_hash_url, tracker).await?;
+    Ok((info_hash, tracker_response.get_peers()))
+}
+
+/// Peers for a magnet link, its `x.pe` peers followed by those of every `tr` tracker
+/// that answers
+async fn magnet_peers(magnet: &Magnet) -> Result<Vec<SocketAddrV4>> {
+    let mut peers = vec![];
+    for peer in &magnet.peers {
+        match tokio::net::lookup_host(peer.as_str()).await {
+            Ok(addrs) => peers.extend(addrs.filter_map(|addr| match addr {
+                SocketAddr::V4(addr) => Some(addr),
+                SocketAddr::V6(_) => None,
+            })),
+            Err(err) => eprintln!("{peer}: {err}"),
+        }
+    }
+    let info_hash = magnet.swarm_hash();
+    let req = TrackerRequest::new(info_hash, MAGNET_LEFT);
+    for tracker in &magnet.trackers {
+        let info_hash_url = TrackerRequest::url_encode(info_hash);
+        match TrackerRequest::request(&req, info_hash_url, tracker).await {
+            Ok(tracker_response) => peers.extend(tracker_response.get_peers()),
+            Err(err) => eprintln!("{tracker}: {err:#}"),
+        }
+    }
+    let mut seen = HashSet::new();
+    peers.retain(|peer| seen.insert(*peer));
+    if peers.is_empty() {
+        bail!("the magnet link's `x.pe` peers and `tr` trackers gave no peers");
+    }
+    Ok(peers)
+}
+
+/// Metainfo for `magnet` from the peer at `socket`: the `info` dictionary, unless an earlier
+/// peer already sent it, and for v2 and hybrid torrents the piece layers the metadata lacks
+async fn fetch_torrent(
+    socket: SocketAddrV4,
+    magnet: &Magnet,
+    info: &mut Option<Vec<u8>>,
+) -> Result<Torrent> {
+    let (mut stream, v2) = Peer::handshake_extended(Peer { socket }, magnet.swarm_hash()).await?;
+    let info = match info {
+        Some(info) => info,
+        None => info.insert(metadata::fetch_metadata(&mut stream, magnet).await?),
+    };
+    let mut torrent = Torrent::from_info_bytes(info, &magnet.trackers)?;
+    if torrent.info.version() != MetaVersion::V1 {
+        if !v2 {
+            bail!("peer does not support BitTorrent v2 to send the piece layers");
+        }
+        torrent.piece_layers = hashes::fetch_piece_layers(&mut stream, &torrent.info).await?;
+        torrent.validate_piece_layers()?;
+    }
+    Ok(torrent)
+}
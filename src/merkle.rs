@@ -0,0 +1,145 @@
+use sha2::{Digest, Sha256};
+
+/// Size of the merkle tree leaves, v2 torrents hash every file in blocks of this size
+/// [spec](http://bittorrent.org/beps/bep_0052.html)
+pub const BLOCK_LENGTH: usize = 16 * 1024;
+
+pub type Hash = [u8; 32];
+
+/// Leaf hash of one block, only a file's last block can be shorter than [`BLOCK_LENGTH`]
+pub fn hash_block(data: &[u8]) -> Hash {
+    Sha256::digest(data).into()
+}
+
+pub fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Root of a tree `width` nodes wide, a power of two, whose first nodes are `nodes` and
+/// whose remaining nodes are all `pad`
+pub fn root(nodes: &[Hash], width: usize, pad: Hash) -> Hash {
+    let mut layer = nodes.to_vec();
+    let mut pad = pad;
+    let mut width = width.max(1);
+    while width > 1 {
+        if layer.len() % 2 == 1 {
+            layer.push(pad);
+        }
+        layer = layer
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+        pad = hash_pair(&pad, &pad);
+        width /= 2;
+    }
+    layer.first().copied().unwrap_or(pad)
+}
+
+/// Root of a subtree of `width` all-zero leaves, which stands in for pieces past the end of a file
+pub fn pad_hash(width: usize) -> Hash {
+    root(&[], width, [0; 32])
+}
+
+/// Leaf hashes of `data`, block by block
+pub fn block_hashes(data: &[u8]) -> Vec<Hash> {
+    data.chunks(BLOCK_LENGTH).map(hash_block).collect()
+}
+
+/// Piece layer hash of one piece, the root over its blocks padded to a full piece
+pub fn piece_hash(data: &[u8], piece_length: u32) -> Hash {
+    let width = piece_length as usize / BLOCK_LENGTH;
+    root(&block_hashes(data), width, [0; 32])
+}
+
+/// `pieces root` of a file no longer than one piece, from its whole content
+pub fn small_file_root(data: &[u8]) -> Hash {
+    let leaves = block_hashes(data);
+    root(&leaves, leaves.len().next_power_of_two(), [0; 32])
+}
+
+/// `pieces root` of a file longer than one piece, from its piece layer
+pub fn file_root(piece_layer: &[Hash], piece_length: u32) -> Hash {
+    let pad = pad_hash(piece_length as usize / BLOCK_LENGTH);
+    root(piece_layer, piece_layer.len().next_power_of_two(), pad)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZERO: Hash = [0; 32];
+
+    /// `blocks` full blocks and a half one, each block filled with its index
+    fn content(blocks: usize) -> Vec<u8> {
+        (0..=blocks)
+            .flat_map(|block| {
+                let length = if block == blocks {
+                    BLOCK_LENGTH / 2
+                } else {
+                    BLOCK_LENGTH
+                };
+                vec![block as u8; length]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pads_with_zero_subtrees() {
+        assert_eq!(pad_hash(1), ZERO);
+        assert_eq!(
+            pad_hash(4),
+            hash_pair(&hash_pair(&ZERO, &ZERO), &hash_pair(&ZERO, &ZERO))
+        );
+    }
+
+    #[test]
+    fn small_file_root_pads_to_a_power_of_two() {
+        let data = content(0);
+        assert_eq!(small_file_root(&data), hash_block(&data));
+        let data = content(2);
+        let leaves = block_hashes(&data);
+        assert_eq!(leaves.len(), 3);
+        assert_eq!(leaves[2], hash_block(&data[2 * BLOCK_LENGTH..]));
+        assert_eq!(
+            small_file_root(&data),
+            hash_pair(
+                &hash_pair(&leaves[0], &leaves[1]),
+                &hash_pair(&leaves[2], &ZERO)
+            )
+        );
+    }
+
+    #[test]
+    fn piece_hash_pads_to_a_full_piece() {
+        let data = content(0);
+        assert_eq!(
+            piece_hash(&data, 2 * BLOCK_LENGTH as u32),
+            hash_pair(&hash_block(&data), &ZERO)
+        );
+    }
+
+    #[test]
+    fn file_root_matches_the_tree_over_every_block() {
+        // 5.5 blocks in pieces of 2 blocks: 3 pieces, the last one half empty
+        let piece_length = 2 * BLOCK_LENGTH as u32;
+        let data = content(5);
+        let layer: Vec<Hash> = data
+            .chunks(piece_length as usize)
+            .map(|piece| piece_hash(piece, piece_length))
+            .collect();
+        assert_eq!(layer.len(), 3);
+        let pieces_root = file_root(&layer, piece_length);
+        assert_eq!(
+            pieces_root,
+            hash_pair(
+                &hash_pair(&layer[0], &layer[1]),
+                &hash_pair(&layer[2], &pad_hash(2))
+            )
+        );
+        // the same root from the 6 blocks padded to 8 leaves
+        assert_eq!(pieces_root, root(&block_hashes(&data), 8, ZERO));
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{bail, ensure, Context, Result};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{
+    bencode_parser::{borrowed, decode_borrowed_with_limits, DecodeLimits, Value},
+    magnet::Magnet,
+};
+
+/// Message id shared by all extension protocol messages
+/// [spec](http://bittorrent.org/beps/bep_0010.html)
+pub const EXTENDED_MESSAGE_ID: u8 = 20;
+/// Extended message id of the extension handshake
+const EXTENDED_HANDSHAKE_ID: u8 = 0;
+/// Extended message id we ask peers to use for the `ut_metadata` messages they send us
+const UT_METADATA_ID: u8 = 1;
+/// Size of every metadata piece except the last
+pub const METADATA_PIECE_LENGTH: usize = 16 * 1024;
+
+const MSG_REQUEST: i64 = 0;
+const MSG_DATA: i64 = 1;
+const MSG_REJECT: i64 = 2;
+
+/// Downloads the bencoded info dictionary from a peer with the `ut_metadata` extension and
+/// checks it against the magnet's info hashes
+/// [spec](http://bittorrent.org/beps/bep_0009.html)
+///
+/// The stream must come from [`Peer::handshake_extended`](crate::peer::Peer::handshake_extended).
+pub async fn fetch_metadata(stream: &mut TcpStream, magnet: &Magnet) -> Result<Vec<u8>> {
+    let limits = DecodeLimits::network();
+    let handshake: Value = [(
+        "m",
+        [("ut_metadata", UT_METADATA_ID as i64)]
+            .into_iter()
+            .collect::<Value>(),
+    )]
+    .into_iter()
+    .collect();
+    send_extended(stream, EXTENDED_HANDSHAKE_ID, &handshake.encode()).await?;
+
+    // the peer may send its bitfield and haves first
+    let (peer_metadata_id, metadata_size) = loop {
+        let (id, payload) = read_message(stream, limits.max_input_size).await?;
+        if id != EXTENDED_MESSAGE_ID || payload.first() != Some(&EXTENDED_HANDSHAKE_ID) {
+            continue;
+        }
+        let (handshake, _) = decode_borrowed_with_limits(&payload[1..], limits)
+            .context("invalid extension handshake")?;
+        let peer_metadata_id = handshake
+            .get("m")
+            .and_then(|m| m.get("ut_metadata"))
+            .and_then(borrowed::Value::as_int)
+            .filter(|&id| id > 0)
+            .context("peer does not support ut_metadata")?;
+        let metadata_size = handshake
+            .get("metadata_size")
+            .and_then(borrowed::Value::as_int)
+            .context("peer did not announce the metadata size")?;
+        break (peer_metadata_id, metadata_size);
+    };
+    let peer_metadata_id =
+        u8::try_from(peer_metadata_id).context("peer sent an invalid ut_metadata id")?;
+    ensure!(
+        metadata_size > 0 && metadata_size as usize <= limits.max_input_size,
+        "peer announced an invalid metadata size of {metadata_size} bytes"
+    );
+    let metadata_size = metadata_size as usize;
+
+    let mut metadata = Vec::with_capacity(metadata_size);
+    let piece_count = metadata_size.div_ceil(METADATA_PIECE_LENGTH);
+    for piece in 0..piece_count {
+        let request: Value = [("msg_type", MSG_REQUEST), ("piece", piece as i64)]
+            .into_iter()
+            .collect();
+        send_extended(stream, peer_metadata_id, &request.encode()).await?;
+        let expected_length = METADATA_PIECE_LENGTH.min(metadata_size - metadata.len());
+        let data = read_metadata_piece(stream, piece, limits).await?;
+        ensure!(
+            data.len() == expected_length,
+            "metadata piece {piece} is {} bytes, expected {expected_length}",
+            data.len()
+        );
+        metadata.extend(data);
+    }
+
+    if let Some(info_hash) = magnet.info_hash {
+        let hash: [u8; 20] = Sha1::digest(&metadata).into();
+        ensure!(
+            hash == info_hash,
+            "metadata hash {} does not match the info hash {}",
+            hex::encode(hash),
+            hex::encode(info_hash)
+        );
+    }
+    if let Some(info_hash_v2) = magnet.info_hash_v2 {
+        let hash: [u8; 32] = Sha256::digest(&metadata).into();
+        ensure!(
+            hash == info_hash_v2,
+            "metadata hash {} does not match the v2 info hash {}",
+            hex::encode(hash),
+            hex::encode(info_hash_v2)
+        );
+    }
+    Ok(metadata)
+}
+
+/// Waits for the `data` message answering our request for `piece`
+async fn read_metadata_piece(
+    stream: &mut TcpStream,
+    piece: usize,
+    limits: DecodeLimits,
+) -> Result<Vec<u8>> {
+    loop {
+        let (id, payload) = read_message(stream, limits.max_input_size).await?;
+        if id != EXTENDED_MESSAGE_ID || payload.first() != Some(&UT_METADATA_ID) {
+            continue;
+        }
+        // the dictionary is followed by the piece data itself
+        let (message, data) = decode_borrowed_with_limits(&payload[1..], limits)
+            .context("invalid ut_metadata message")?;
+        let msg_type = message.get("msg_type").and_then(borrowed::Value::as_int);
+        let index = message.get("piece").and_then(borrowed::Value::as_int);
+        match msg_type {
+            Some(MSG_DATA) if index == Some(piece as i64) => return Ok(data.to_vec()),
+            Some(MSG_REJECT) if index == Some(piece as i64) => {
+                bail!("peer rejected the request for metadata piece {piece}")
+            }
+            // we have no metadata to give, so the peer's own requests go unanswered
+            _ => continue,
+        }
+    }
+}
+
+async fn send_extended(stream: &mut TcpStream, extended_id: u8, payload: &[u8]) -> Result<()> {
+    let mut message = Vec::with_capacity(payload.len() + 6);
+    message.extend(((payload.len() + 2) as u32).to_be_bytes());
+    message.push(EXTENDED_MESSAGE_ID);
+    message.push(extended_id);
+    message.extend(payload);
+    stream.write_all(&message).await?;
+    Ok(())
+}
+
+/// Next message as its id and payload, skipping keep-alives
+pub(crate) async fn read_message(
+    stream: &mut TcpStream,
+    max_length: usize,
+) -> Result<(u8, Vec<u8>)> {
+    loop {
+        let mut length_buf = [0; 4];
+        stream.read_exact(&mut length_buf).await?;
+        let length = u32::from_be_bytes(length_buf) as usize;
+        if length == 0 {
+            continue;
+        }
+        ensure!(
+            length <= max_length,
+            "peer sent a {length} byte message, more than the {max_length} byte limit"
+        );
+        let mut message = vec![0; length];
+        stream.read_exact(&mut message).await?;
+        let payload = message.split_off(1);
+        return Ok((message[0], payload));
+    }
+}
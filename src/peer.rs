@@ -1,14 +1,17 @@
 use std::{
-    collections::{HashMap, HashSet},
-    net::SocketAddrV4,
+    collections::{HashMap, HashSet, VecDeque},
+    net::{SocketAddr, SocketAddrV4},
     path::PathBuf,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
+use anyhow::{ensure, Context, Result};
 use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
 use tokio::{
-    fs::OpenOptions,
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
     sync::Mutex,
@@ -16,15 +19,48 @@ use tokio::{
     time::sleep,
 };
 
-use crate::{torrent::Torrent, DEFAULT_BLOCK_LENGTH, MAX_CONCURRENT_REQUESTS};
+use crate::{
+    hashes::{BlockVerifier, PieceHashes},
+    storage::Storage,
+    torrent::Torrent,
+    webseed::WebSeed,
+    DEFAULT_BLOCK_LENGTH, MAX_CONCURRENT_REQUESTS,
+};
+
+/// Reserved byte and bit of the handshake that advertise the extension protocol
+/// [spec](http://bittorrent.org/beps/bep_0010.html)
+const EXTENSION_PROTOCOL_BYTE: usize = 5;
+const EXTENSION_PROTOCOL_BIT: u8 = 0x10;
+/// Reserved byte and bit of the handshake that advertise BitTorrent v2
+/// [spec](http://bittorrent.org/beps/bep_0052.html)
+const V2_PROTOCOL_BYTE: usize = 7;
+const V2_PROTOCOL_BIT: u8 = 0x10;
+/// Times a block is asked for before giving up on a piece whose block keeps failing its
+/// merkle check
+const MAX_BLOCK_ATTEMPTS: usize = 3;
+
+/// Downloaded piece data keyed by piece index
+pub type PieceBuffer = Arc<Mutex<HashMap<u32, Vec<u8>>>>;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// Which pieces `Peer::download_torrent` fetches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pieces {
+    /// Every piece, into the torrent's files
+    All,
+    /// One piece, written on its own to the output path
+    Single(u32),
+    /// Only these pieces, into the torrent's files, which are kept when they already exist
+    Listed(Vec<u32>),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub struct Peer {
     pub socket: SocketAddrV4,
 }
 
 impl Peer {
     //TODO: refactor arguments
+    #[allow(clippy::too_many_arguments)]
     async fn request_block(
         stream: Arc<Mutex<TcpStream>>,
         piece_index: u32,
@@ -32,9 +68,10 @@ impl Peer {
         offset: u32,
         block_task_id: u64,
         current_block_tasks: Arc<Mutex<HashSet<u64>>>,
-        download_piece_buf: Arc<Mutex<HashMap<u32, Vec<u8>>>>,
+        download_piece_buf: PieceBuffer,
         block_index: u32,
-    ) {
+        block_hashes: Option<Arc<PieceHashes>>,
+    ) -> Result<bool> {
         println!(
             "--------------- started b {}, for {}",
             block_task_id, piece_index
@@ -43,6 +80,42 @@ impl Peer {
         //     println!("huge sleep for b {}", block_task_id);
         //     sleep(tokio::time::Duration::from_millis(1000)).await;
         // }
+        // the slot is freed whether or not the block arrived
+        let received = Self::receive_block(&stream, piece_index, piece_length, offset).await;
+        let mut temp_current_block_tasks = current_block_tasks.lock().await;
+        println!("pending tasks: {:?}", temp_current_block_tasks);
+        temp_current_block_tasks.remove(&block_task_id);
+        println!("pending tasks: {:?}", temp_current_block_tasks);
+        drop(temp_current_block_tasks);
+        let (block_data, peer) = received?;
+
+        // a v2 block is checked on its own, so only the block is lost when it is bad
+        let verified = block_hashes
+            .as_ref()
+            .is_none_or(|hashes| hashes.check_block(block_index, &block_data));
+        if verified {
+            let mut d_buf = download_piece_buf.lock().await;
+            d_buf.insert(block_index, block_data);
+            println!("dp of {piece_index}: {:?}", d_buf.len());
+        } else {
+            eprintln!(
+                "block {block_index} of piece {piece_index} from {peer} failed its merkle check"
+            );
+        }
+        println!(
+            "--------------- ended b {}, for {}",
+            block_task_id, piece_index
+        );
+        Ok(verified)
+    }
+
+    /// Requests the block at `offset` of a piece and reads it, with the address it came from
+    async fn receive_block(
+        stream: &Mutex<TcpStream>,
+        piece_index: u32,
+        piece_length: u32,
+        offset: u32,
+    ) -> Result<(Vec<u8>, SocketAddr)> {
         let mut stream = stream.lock().await;
         println!("{},{},{}", piece_length, offset, DEFAULT_BLOCK_LENGTH);
         // This'll be 2^14 (16 * 1024) for all blocks except the last one.
@@ -61,85 +134,99 @@ impl Peer {
         request_message[9..13].copy_from_slice(&(offset.to_be_bytes()));
         request_message[13..17].copy_from_slice(&(block_length.to_be_bytes()));
         println!("{:?}", request_message);
-        stream.write_all(&request_message).await.unwrap();
+        stream.write_all(&request_message).await?;
 
         // Recieve piece data
         let mut piece_header = vec![0; 13];
-        stream.read_exact(&mut piece_header).await.unwrap();
+        stream.read_exact(&mut piece_header).await?;
         println!(
             "ph: {:?}",
             u32::from_be_bytes(piece_header[0..4].try_into().unwrap())
         );
-        assert_eq!(piece_header[4], 7);
+        ensure!(
+            piece_header[4] == 7,
+            "expected a piece message, got message ID {}",
+            piece_header[4]
+        );
         let mut block_data = vec![0; block_length as usize];
-        stream.read_exact(&mut block_data).await.unwrap();
+        stream.read_exact(&mut block_data).await?;
         println!("bl len: {:?}", block_data.len());
         println!("off: {}", offset);
-
-        let mut d_buf = download_piece_buf.lock().await;
-        d_buf.insert(block_index, block_data);
-        println!("dp of {piece_index}: {:?}", d_buf.len());
-
-        let mut temp_current_block_tasks = current_block_tasks.lock().await;
-        println!("pending tasks: {:?}", temp_current_block_tasks);
-        temp_current_block_tasks.remove(&block_task_id);
-        println!("pending tasks: {:?}", temp_current_block_tasks);
-        println!(
-            "--------------- ended b {}, for {}",
-            block_task_id, piece_index
-        );
+        Ok((block_data, stream.peer_addr()?))
     }
 
+    /// Downloads and verifies one piece, returning its data, or an error when the peer keeps
+    /// sending bad data so the piece can be asked of someone else
     pub async fn download_piece(
         stream: Arc<Mutex<TcpStream>>,
         piece_index: u32,
         piece_length: u32,
         current_block_tasks: Arc<Mutex<HashSet<u64>>>,
-        piece_hash: String,
-        whole_file_buf: Option<Arc<Mutex<HashMap<u32, Vec<u8>>>>>,
-        output_path: PathBuf,
-    ) {
+        piece_hash: Option<String>,
+        verifier: Option<Arc<BlockVerifier>>,
+    ) -> Result<Vec<u8>> {
         println!("***** started piece download {}", piece_index);
+        let block_hashes = match &verifier {
+            Some(verifier) => Some(Arc::new(
+                verifier
+                    .piece_hashes(&stream, piece_index)
+                    .await
+                    .with_context(|| format!("no block hashes for piece {piece_index}"))?,
+            )),
+            None => None,
+        };
         // sending peer details request
         // dividing pieces into blocks
-        let mut offset: u32 = 0;
-        let mut tasks = vec![];
         let download_piece_buf = Arc::new(Mutex::new(HashMap::new()));
-        let mut block_index = 0;
+        let block_count = piece_length.div_ceil(DEFAULT_BLOCK_LENGTH);
+        let mut pending: Vec<u32> = (0..block_count).collect();
 
-        while offset < piece_length {
-            let block_task_id = rand::random();
-            let mut temp_current_block_tasks = current_block_tasks.lock().await;
-            while temp_current_block_tasks.len() >= MAX_CONCURRENT_REQUESTS {
-                println!("max reached b waiting {}", block_task_id);
+        for _ in 0..MAX_BLOCK_ATTEMPTS {
+            let mut tasks = vec![];
+            for &block in &pending {
+                let block_task_id = rand::random();
+                let mut temp_current_block_tasks = current_block_tasks.lock().await;
+                while temp_current_block_tasks.len() >= MAX_CONCURRENT_REQUESTS {
+                    println!("max reached b waiting {}", block_task_id);
+                    drop(temp_current_block_tasks);
+                    sleep(tokio::time::Duration::from_millis(100)).await;
+                    temp_current_block_tasks = current_block_tasks.lock().await;
+                }
+                temp_current_block_tasks.insert(block_task_id);
                 drop(temp_current_block_tasks);
-                sleep(tokio::time::Duration::from_millis(100)).await;
-                temp_current_block_tasks = current_block_tasks.lock().await;
+                let task = task::spawn(Self::request_block(
+                    stream.clone(),
+                    piece_index,
+                    piece_length,
+                    block * DEFAULT_BLOCK_LENGTH,
+                    block_task_id,
+                    current_block_tasks.clone(),
+                    download_piece_buf.clone(),
+                    block,
+                    block_hashes.clone(),
+                ));
+                tasks.push((block, task));
+            }
+            let mut failed = vec![];
+            for (block, task) in tasks {
+                if !task.await?? {
+                    failed.push(block);
+                }
+            }
+            pending = failed;
+            if pending.is_empty() {
+                break;
             }
-            temp_current_block_tasks.insert(block_task_id);
-            drop(temp_current_block_tasks);
-            let task = task::spawn(Self::request_block(
-                stream.clone(),
-                piece_index,
-                piece_length,
-                offset,
-                block_task_id,
-                current_block_tasks.clone(),
-                download_piece_buf.clone(),
-                block_index,
-            ));
-            tasks.push(task);
-            offset += DEFAULT_BLOCK_LENGTH;
-            block_index += 1;
-        }
-        for task in tasks {
-            task.await.unwrap();
         }
+        ensure!(
+            pending.is_empty(),
+            "blocks {pending:?} of piece {piece_index} failed their merkle check {MAX_BLOCK_ATTEMPTS} times"
+        );
         let block_data_hashmap = download_piece_buf.lock().await;
         let mut piece_data_buf = Vec::with_capacity(piece_length.try_into().unwrap());
         let mut o = 0;
         loop {
-            if o == block_index {
+            if o == block_count {
                 break;
             }
             let block_data = block_data_hashmap.get(&o).unwrap();
@@ -150,56 +237,75 @@ impl Peer {
             );
             o += 1;
         }
-        let piece_buf_hash = Sha1::digest(piece_data_buf.clone());
-        assert_eq!(
-            piece_hash,
-            hex::encode(piece_buf_hash), //NOTE: not sure if comparing hex values of hashes is good
-            "integrity of piece {piece_index} failed"
-        );
-        match whole_file_buf {
-            Some(buf) => {
-                let mut b = buf.lock().await;
-                b.insert(piece_index, piece_data_buf);
-            }
-            None => {
-                let mut file = OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(output_path)
-                    .await
-                    .expect("failed to open file");
-
-                file.write_all(&piece_data_buf)
-                    .await
-                    .expect("failed to write to file");
-                println!("file written from piece");
-            }
+        // v2-only torrents have no SHA-1 piece hashes, their blocks were checked one by one
+        if let Some(piece_hash) = piece_hash {
+            let piece_buf_hash = Sha1::digest(piece_data_buf.clone());
+            ensure!(
+                piece_hash == hex::encode(piece_buf_hash), //NOTE: not sure if comparing hex values of hashes is good
+                "integrity of piece {piece_index} failed"
+            );
         }
+        Ok(piece_data_buf)
     }
 
     pub async fn handshake(peer: Peer, info_hash: [u8; 20]) -> TcpStream {
-        let mut stream = TcpStream::connect(peer.socket).await.unwrap();
+        let (stream, _) = Self::exchange_handshake(peer, info_hash, [0; 8])
+            .await
+            .unwrap();
+        stream
+    }
+    /// Handshake advertising the extension protocol, for peers that have to send us
+    /// extension messages such as `ut_metadata`. It advertises BitTorrent v2 as well, as the
+    /// metadata may turn out to be v2, and returns whether the peer speaks it.
+    pub async fn handshake_extended(peer: Peer, info_hash: [u8; 20]) -> Result<(TcpStream, bool)> {
+        let mut reserved = [0; 8];
+        reserved[EXTENSION_PROTOCOL_BYTE] |= EXTENSION_PROTOCOL_BIT;
+        reserved[V2_PROTOCOL_BYTE] |= V2_PROTOCOL_BIT;
+        let (stream, peer_reserved) = Self::exchange_handshake(peer, info_hash, reserved).await?;
+        ensure!(
+            peer_reserved[EXTENSION_PROTOCOL_BYTE] & EXTENSION_PROTOCOL_BIT != 0,
+            "peer does not support the extension protocol"
+        );
+        Ok((
+            stream,
+            peer_reserved[V2_PROTOCOL_BYTE] & V2_PROTOCOL_BIT != 0,
+        ))
+    }
+    /// Handshake advertising BitTorrent v2, also returning whether the peer speaks it and
+    /// so answers hash requests
+    pub async fn handshake_v2(peer: Peer, info_hash: [u8; 20]) -> Result<(TcpStream, bool)> {
+        let mut reserved = [0; 8];
+        reserved[V2_PROTOCOL_BYTE] |= V2_PROTOCOL_BIT;
+        let (stream, peer_reserved) = Self::exchange_handshake(peer, info_hash, reserved).await?;
+        Ok((
+            stream,
+            peer_reserved[V2_PROTOCOL_BYTE] & V2_PROTOCOL_BIT != 0,
+        ))
+    }
+    /// Sends our handshake and reads the peer's, returning the peer's reserved bytes
+    async fn exchange_handshake(
+        peer: Peer,
+        info_hash: [u8; 20],
+        reserved: [u8; 8],
+    ) -> std::io::Result<(TcpStream, [u8; 8])> {
+        let mut stream = TcpStream::connect(peer.socket).await?;
         let peer_id: [u8; 20] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9];
         let mut buffer: Vec<u8> = Vec::with_capacity(68);
         buffer.push(19);
         buffer.extend("BitTorrent protocol".as_bytes());
-        buffer.extend(&[0_u8; 8]);
+        buffer.extend(&reserved);
         buffer.extend(&info_hash);
         buffer.extend(&peer_id);
-        stream.write_all(&buffer).await.unwrap();
-        stream.read_exact(&mut buffer).await.unwrap();
+        stream.write_all(&buffer).await?;
+        stream.read_exact(&mut buffer).await?;
         let peer_id = &buffer[48..];
         let phex = hex::encode(peer_id);
         println!("hex Peer ID: {}", phex);
-        stream
+        let peer_reserved = buffer[20..28].try_into().unwrap();
+        Ok((stream, peer_reserved))
     }
-    pub async fn download_torrent(
-        mut stream: TcpStream,
-        torrent: Torrent,
-        piece_hashes: Vec<String>,
-        output_path: PathBuf,
-        only_piece: Option<u32>,
-    ) {
+    /// Waits for the peer's bitfield, declares interest and waits to be unchoked
+    async fn start_requesting(mut stream: TcpStream) -> Arc<Mutex<TcpStream>> {
         // getting the bitfield
         // getting bitfield message prefix that indicates the total size of the bitfield message
         // excluding itself(4 bytes)
@@ -241,72 +347,197 @@ impl Peer {
                 unchoke_buf[4]
             );
         }
-        let mut piece_index = only_piece.unwrap_or_default();
-        let mut total_len = 0;
-        let file_len: u32 = torrent.info.length.unwrap() as u32;
-        let total_pieces = torrent.info.pieces.len() / 20;
-        let p_size = torrent.info.piece_length;
+        Arc::new(Mutex::new(stream))
+    }
+    /// Downloads `pieces` of the torrent from the peer and the web seeds together;
+    /// either may be missing but not both. A piece the peer fails to deliver goes back in the
+    /// queue for the web seeds, and the peer is not asked for more.
+    pub async fn download_torrent(
+        stream: Option<TcpStream>,
+        torrent: Torrent,
+        piece_hashes: Vec<String>,
+        output_path: PathBuf,
+        pieces: Pieces,
+        verify_blocks: bool,
+        web_seeds: Vec<WebSeed>,
+    ) -> Result<()> {
+        let stream = match stream {
+            Some(stream) => Some(Self::start_requesting(stream).await),
+            None => None,
+        };
         let pending_tasks: Arc<Mutex<HashSet<u64>>> = Arc::new(Mutex::new(HashSet::new()));
-        let stream: Arc<Mutex<TcpStream>> = Arc::new(Mutex::new(stream));
-        let whole_file_buf_lock = Arc::new(Mutex::new(HashMap::new()));
+        let verifier = verify_blocks.then(|| Arc::new(BlockVerifier::new(&torrent)));
 
-        let mut ptasks = vec![];
-        loop {
-            println!("i set to {} tp: {}", piece_index, total_pieces);
-            if piece_index == (total_pieces as u32) {
-                break;
-            }
-            total_len += p_size;
-            let temp_file_len = if total_len > file_len {
-                // last piece
-                p_size - (total_len - file_len)
-            } else {
-                p_size
+        if let Pieces::Single(piece_index) = pieces {
+            let piece_data = match stream {
+                Some(stream) => {
+                    let piece_hash = piece_hashes.get(piece_index as usize).cloned();
+                    Self::download_piece(
+                        stream,
+                        piece_index,
+                        torrent.info.piece_size(piece_index),
+                        pending_tasks,
+                        piece_hash,
+                        verifier,
+                    )
+                    .await?
+                }
+                None => {
+                    let client = reqwest::Client::new();
+                    let mut piece_data = None;
+                    for seed in &web_seeds {
+                        match seed.download_piece(&client, &torrent, piece_index).await {
+                            Ok(data) => {
+                                piece_data = Some(data);
+                                break;
+                            }
+                            Err(err) => eprintln!("web seed {seed}: {err:#}"),
+                        }
+                    }
+                    piece_data.context("no web seed could provide the piece")?
+                }
             };
-            println!("fl set to {} tl: {}", file_len, total_len);
-            let piece_hash = piece_hashes.get(piece_index as usize).unwrap();
-            let ptask = task::spawn(Self::download_piece(
-                Arc::clone(&stream),
-                piece_index,
-                temp_file_len,
-                pending_tasks.clone(),
-                piece_hash.to_string(),
-                if only_piece.is_some() {
-                    None
-                } else {
-                    Some(whole_file_buf_lock.clone())
-                },
-                output_path.clone(),
-            ));
-            println!("pushed task {}", piece_index);
-            ptasks.push(ptask);
-
-            piece_index += 1;
+            tokio::fs::write(&output_path, piece_data)
+                .await
+                .with_context(|| format!("failed to write {}", output_path.display()))?;
+            println!("file written from piece");
+            return Ok(());
         }
-        for ptask in ptasks {
-            ptask.await.unwrap();
+
+        let storage = Arc::new(Storage::new(&torrent.info, &output_path));
+        storage
+            .allocate()
+            .await
+            .context("failed to create output files")?;
+        let total_pieces = torrent.info.piece_count();
+        // the peer and every web seed take pieces from the front as they have room for them
+        let queue = Arc::new(Mutex::new(match pieces {
+            Pieces::Listed(pieces) => VecDeque::from(pieces),
+            _ => (0..total_pieces as u32).collect(),
+        }));
+        let torrent = Arc::new(torrent);
+        let seed_tasks: Vec<_> = web_seeds
+            .into_iter()
+            .map(|seed| task::spawn(seed.run(torrent.clone(), storage.clone(), queue.clone())))
+            .collect();
+
+        let peer_failed = Arc::new(AtomicBool::new(false));
+        let download_from_peer = || async {
+            let Some(stream) = &stream else {
+                return;
+            };
+            let mut ptasks = VecDeque::new();
+            while !peer_failed.load(Ordering::Relaxed) {
+                let Some(piece_index) = queue.lock().await.pop_front() else {
+                    break;
+                };
+                println!("i set to {} tp: {}", piece_index, total_pieces);
+                let piece_hash = piece_hashes.get(piece_index as usize).cloned();
+                let piece_task = Self::download_piece(
+                    Arc::clone(stream),
+                    piece_index,
+                    torrent.info.piece_size(piece_index),
+                    pending_tasks.clone(),
+                    piece_hash,
+                    verifier.clone(),
+                );
+                let storage = storage.clone();
+                let queue = queue.clone();
+                let peer_failed = peer_failed.clone();
+                let ptask = task::spawn(async move {
+                    let downloaded = async {
+                        let piece_data = piece_task.await?;
+                        storage
+                            .write_piece(piece_index, &piece_data)
+                            .await
+                            .with_context(|| format!("failed to write piece {piece_index}"))
+                    };
+                    // the piece is left to the web seeds and the peer is not asked again
+                    if let Err(err) = downloaded.await {
+                        eprintln!("piece {piece_index}: {err:#}");
+                        queue.lock().await.push_back(piece_index);
+                        peer_failed.store(true, Ordering::Relaxed);
+                    }
+                });
+                println!("pushed task {}", piece_index);
+                ptasks.push_back(ptask);
+                if ptasks.len() >= MAX_CONCURRENT_REQUESTS {
+                    ptasks.pop_front().unwrap().await.unwrap();
+                }
+            }
+            for ptask in ptasks {
+                ptask.await.unwrap();
+            }
+        };
+        download_from_peer().await;
+        for seed_task in seed_tasks {
+            seed_task.await.unwrap();
         }
+        // pieces a failing web seed put back after the peer ran out of work
+        download_from_peer().await;
+        let missing = queue.lock().await.len();
+        ensure!(
+            missing == 0,
+            "{missing} pieces could not be downloaded from the peer or any web seed"
+        );
+        println!("whole file written");
+        Ok(())
+    }
+}
 
-        let mut temp = 0;
-        loop {
-            if temp == piece_index {
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// A peer answering every block request with zeros
+    async fn serve_zeros(listener: TcpListener) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = [0; 17];
+        while stream.read_exact(&mut request).await.is_ok() {
+            let length = u32::from_be_bytes(request[13..17].try_into().unwrap());
+            let mut message = (9 + length).to_be_bytes().to_vec();
+            message.push(7);
+            message.extend(&request[5..13]);
+            message.resize(13 + length as usize, 0);
+            if stream.write_all(&message).await.is_err() {
                 break;
             }
-            let whole_file_buf = whole_file_buf_lock.lock().await;
-            if whole_file_buf.len() > 0 {
-                let mut file = OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(&output_path)
-                    .await
-                    .expect("failed to open file");
-                let p_buf = &whole_file_buf.get(&temp).unwrap();
-                file.write_all(p_buf)
-                    .await
-                    .expect("failed to write to file");
-                println!("whole file written");
-                temp += 1;
-            };
         }
     }
+
+    async fn download_zeros(piece_length: u32, piece_hash: [u8; 20]) -> Result<Vec<u8>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(serve_zeros(listener));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        Peer::download_piece(
+            Arc::new(Mutex::new(stream)),
+            0,
+            piece_length,
+            Arc::new(Mutex::new(HashSet::new())),
+            Some(hex::encode(piece_hash)),
+            None,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn downloads_a_piece_in_blocks() {
+        let piece_length = DEFAULT_BLOCK_LENGTH + 100;
+        let zeros = vec![0; piece_length as usize];
+        let piece = download_zeros(piece_length, Sha1::digest(&zeros).into())
+            .await
+            .unwrap();
+        assert_eq!(piece, zeros);
+    }
+
+    #[tokio::test]
+    async fn fails_on_a_corrupt_piece() {
+        let err = download_zeros(DEFAULT_BLOCK_LENGTH + 100, [1; 20])
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "integrity of piece 0 failed");
+    }
 }
@@ -0,0 +1,279 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::torrent::{MetaVersion, Torrent};
+
+/// Everything the `info` command shows about a torrent, serialized as is for `--json`
+#[derive(Debug, Clone, Serialize)]
+pub struct TorrentReport {
+    pub name: String,
+    /// `v1`, `v2` or `hybrid`
+    pub meta_version: String,
+    /// Hex v1 info hash, absent for v2-only torrents
+    pub info_hash: Option<String>,
+    /// Hex v2 info hash, absent for v1 torrents
+    pub info_hash_v2: Option<String>,
+    pub total_length: u64,
+    pub piece_length: u32,
+    pub piece_count: usize,
+    pub last_piece_length: u32,
+    pub private: bool,
+    pub source: Option<String>,
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+    /// Seconds since the Unix epoch
+    pub creation_date: Option<u64>,
+    pub encoding: Option<String>,
+    pub announce: Option<String>,
+    /// As [`Torrent::trackers`]
+    pub trackers: Vec<Vec<String>>,
+    pub url_list: Vec<String>,
+    pub httpseeds: Vec<String>,
+    /// Files other than padding
+    pub files: Vec<FileReport>,
+    /// Number of BEP 47 padding files left out of `files`
+    pub padding_files: usize,
+    /// Hex SHA-1 of every piece, empty for v2-only torrents
+    pub piece_hashes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport {
+    pub path: Vec<String>,
+    pub length: u64,
+    /// BEP 47 attributes such as `x` for executable
+    pub attr: Option<String>,
+    /// As [`TorrentFile::symlink_path`](crate::torrent::TorrentFile::symlink_path)
+    pub symlink_path: Option<Vec<String>>,
+    /// Hex root of the file's v2 merkle tree
+    pub pieces_root: Option<String>,
+}
+
+impl TorrentReport {
+    pub fn new(torrent: &Torrent) -> TorrentReport {
+        let info = &torrent.info;
+        let version = info.version();
+        let piece_count = info.piece_count();
+        TorrentReport {
+            name: info.name.clone(),
+            meta_version: version.to_string(),
+            info_hash: (version != MetaVersion::V2).then(|| hex::encode(torrent.info_hash())),
+            info_hash_v2: torrent.info_hash_v2().map(hex::encode),
+            total_length: info.total_length(),
+            piece_length: info.piece_length,
+            piece_count,
+            last_piece_length: piece_count
+                .checked_sub(1)
+                .map_or(0, |last| info.piece_size(last as u32)),
+            private: info.is_private(),
+            source: info.source.clone(),
+            comment: torrent.comment.clone(),
+            created_by: torrent.created_by.clone(),
+            creation_date: torrent.creation_date,
+            encoding: torrent.encoding.clone(),
+            announce: torrent.announce.clone(),
+            trackers: torrent.trackers(),
+            url_list: torrent.url_list.clone(),
+            httpseeds: torrent.httpseeds.clone(),
+            files: Self::files(torrent),
+            padding_files: info
+                .files
+                .iter()
+                .flatten()
+                .filter(|file| file.is_padding())
+                .count(),
+            piece_hashes: torrent.get_piece_hashes(),
+        }
+    }
+    /// The v1 file list without padding files, or the v2 file tree for v2-only torrents
+    fn files(torrent: &Torrent) -> Vec<FileReport> {
+        let info = &torrent.info;
+        let v2_root = |path: &[String]| {
+            info.file_tree
+                .iter()
+                .flatten()
+                .find(|file| file.path == path)
+                .and_then(|file| file.pieces_root)
+                .map(hex::encode)
+        };
+        match (&info.files, &info.file_tree, info.length) {
+            (Some(files), _, _) => files
+                .iter()
+                .filter(|file| !file.is_padding())
+                .map(|file| FileReport {
+                    path: file.path.clone(),
+                    length: file.length as u64,
+                    attr: file.attr.clone(),
+                    symlink_path: file.symlink_path.clone(),
+                    pieces_root: v2_root(&file.path),
+                })
+                .collect(),
+            (None, Some(tree), None) => tree
+                .iter()
+                .map(|file| FileReport {
+                    path: file.path.clone(),
+                    length: file.length,
+                    attr: file.attr.clone(),
+                    symlink_path: file.symlink_path.clone(),
+                    pieces_root: file.pieces_root.map(hex::encode),
+                })
+                .collect(),
+            (None, _, length) => {
+                let path = vec![info.name.clone()];
+                vec![FileReport {
+                    pieces_root: v2_root(&path),
+                    path,
+                    length: length.unwrap_or_default() as u64,
+                    attr: None,
+                    symlink_path: None,
+                }]
+            }
+        }
+    }
+}
+
+impl fmt::Display for TorrentReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Name: {}", self.name)?;
+        if let Some(announce) = &self.announce {
+            writeln!(f, "Tracker URL: {announce}")?;
+        }
+        writeln!(f, "Length: {}", with_size(self.total_length))?;
+        writeln!(f, "Meta Version: {}", self.meta_version)?;
+        if let Some(info_hash) = &self.info_hash {
+            writeln!(f, "Info Hash: {info_hash}")?;
+        }
+        if let Some(info_hash_v2) = &self.info_hash_v2 {
+            writeln!(f, "Info Hash v2: {info_hash_v2}")?;
+        }
+        writeln!(f, "Piece Length: {}", with_size(self.piece_length as u64))?;
+        writeln!(
+            f,
+            "Pieces: {}, the last one {}",
+            self.piece_count,
+            with_size(self.last_piece_length as u64)
+        )?;
+        writeln!(f, "Private: {}", if self.private { "yes" } else { "no" })?;
+        for (label, value) in [
+            ("Source", &self.source),
+            ("Comment", &self.comment),
+            ("Created By", &self.created_by),
+            ("Encoding", &self.encoding),
+        ] {
+            if let Some(value) = value {
+                writeln!(f, "{label}: {value}")?;
+            }
+        }
+        if let Some(date) = self.creation_date {
+            writeln!(f, "Creation Date: {}", format_date(date))?;
+        }
+        writeln!(f, "Trackers:")?;
+        for (tier, trackers) in self.trackers.iter().enumerate() {
+            writeln!(f, "  tier {}: {}", tier + 1, trackers.join(", "))?;
+        }
+        if !self.url_list.is_empty() || !self.httpseeds.is_empty() {
+            writeln!(f, "Web Seeds:")?;
+            for url in &self.url_list {
+                writeln!(f, "  {url}")?;
+            }
+            for url in &self.httpseeds {
+                writeln!(f, "  {url} (httpseed)")?;
+            }
+        }
+        write!(f, "Files: {}", self.files.len())?;
+        if self.padding_files > 0 {
+            write!(f, " and {} padding files", self.padding_files)?;
+        }
+        writeln!(f)?;
+        let width = self
+            .files
+            .iter()
+            .map(|file| with_size(file.length).len())
+            .max()
+            .unwrap_or_default();
+        for file in &self.files {
+            write!(
+                f,
+                "  {:>width$}  {}",
+                with_size(file.length),
+                file.path.join("/")
+            )?;
+            if let Some(target) = &file.symlink_path {
+                write!(f, " -> {}", target.join("/"))?;
+            }
+            writeln!(f)?;
+        }
+        if !self.piece_hashes.is_empty() {
+            writeln!(f, "Piece Hashes:")?;
+            for hash in &self.piece_hashes {
+                writeln!(f, "{hash}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `bytes` followed by the size in binary units, e.g. `1536 (1.5 KiB)`
+fn with_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{bytes} ({value:.1} {})", UNITS[unit])
+}
+
+/// `YYYY-MM-DD HH:MM:SS UTC` for a Unix timestamp
+fn format_date(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    // days to a proleptic Gregorian date, counting eras of 400 years from 0000-03-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_dates_in_utc() {
+        assert_eq!(format_date(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_date(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_date(1_709_251_199), "2024-02-29 23:59:59 UTC");
+        assert_eq!(format_date(4_102_444_800), "2100-01-01 00:00:00 UTC");
+    }
+
+    #[test]
+    fn formats_sizes_in_binary_units() {
+        assert_eq!(with_size(0), "0");
+        assert_eq!(with_size(1023), "1023");
+        assert_eq!(with_size(1536), "1536 (1.5 KiB)");
+        assert_eq!(with_size(3 << 20), "3145728 (3.0 MiB)");
+        assert_eq!(with_size(u64::MAX), "18446744073709551615 (16384.0 PiB)");
+    }
+}
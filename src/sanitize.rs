@@ -0,0 +1,250 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Device names Windows reserves in every directory, whatever the extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+/// Longest file name common filesystems accept, in bytes
+const MAX_NAME_LENGTH: usize = 255;
+/// Replaces characters and names that cannot be used
+const REPLACEMENT: char = '_';
+
+/// A name from metainfo made safe to use as one component of a path on any common
+/// filesystem, `None` for components that would only navigate: empty, `.` and `..`
+pub fn component(name: &str) -> Option<String> {
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+    let mut safe: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*') {
+                REPLACEMENT
+            } else {
+                c
+            }
+        })
+        .collect();
+    // Windows drops trailing dots and spaces, so `a.` and `a` would be the same file
+    safe.truncate(safe.trim_end_matches(['.', ' ']).len());
+    if safe.is_empty() {
+        return Some(REPLACEMENT.to_string());
+    }
+    let stem = safe.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        safe.insert(stem.len(), REPLACEMENT);
+    }
+    if safe.len() > MAX_NAME_LENGTH {
+        let mut end = MAX_NAME_LENGTH;
+        while !safe.is_char_boundary(end) {
+            end -= 1;
+        }
+        safe.truncate(end);
+    }
+    Some(safe)
+}
+
+/// Relative path of safe components, `_` when none is left
+pub fn relative_path(components: &[String]) -> PathBuf {
+    let path: PathBuf = components
+        .iter()
+        .filter_map(|name| component(name))
+        .collect();
+    if path.as_os_str().is_empty() {
+        PathBuf::from(REPLACEMENT.to_string())
+    } else {
+        path
+    }
+}
+
+/// Turns the paths of a torrent's files into safe relative paths, renaming files and
+/// directories whose path collides with an earlier file or directory on a case-insensitive
+/// filesystem
+#[derive(Debug, Default)]
+pub struct PathSanitizer {
+    /// Lowercased paths of the files handed out so far
+    files: HashSet<String>,
+    /// Lowercased paths of the directories handed out so far
+    directories: HashSet<String>,
+    /// Path handed out for each directory, by its lowercased path in the torrent
+    renamed: HashMap<String, Vec<String>>,
+}
+
+impl PathSanitizer {
+    pub fn file_path(&mut self, components: &[String]) -> PathBuf {
+        let path = relative_path(components);
+        let mut components: Vec<String> = path
+            .iter()
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect();
+        let name = components.pop().unwrap_or_default();
+        let mut parent: Vec<String> = vec![];
+        for depth in 1..=components.len() {
+            let original = folded(&components[..depth]);
+            parent = match self.renamed.get(&original) {
+                Some(renamed) => renamed.clone(),
+                None => {
+                    // a directory can share its path with other directories but not a file
+                    let name = first_free(&parent, &components[depth - 1], |path| {
+                        self.files.contains(path)
+                    });
+                    parent.push(name);
+                    self.directories.insert(folded(&parent));
+                    self.renamed.insert(original, parent.clone());
+                    parent
+                }
+            };
+        }
+        let name = first_free(&parent, &name, |path| {
+            self.files.contains(path) || self.directories.contains(path)
+        });
+        parent.push(name);
+        self.files.insert(folded(&parent));
+        parent.iter().collect()
+    }
+}
+
+/// `name`, or the first numbered copy of it, whose path below `parent` is not `taken`
+fn first_free(parent: &[String], name: &str, taken: impl Fn(&str) -> bool) -> String {
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let mut candidate = name.to_string();
+    let mut copy = 1;
+    loop {
+        let path = folded(&[parent, std::slice::from_ref(&candidate)].concat());
+        if !taken(&path) {
+            return candidate;
+        }
+        candidate = format!("{stem}{REPLACEMENT}{copy}{extension}");
+        copy += 1;
+    }
+}
+
+fn folded(components: &[String]) -> String {
+    components.join("/").to_lowercase()
+}
+
+/// Fails if `path`, or a directory on its way below `root`, is a symlink, so writes can only
+/// land in files under `root`
+pub fn check_no_symlinks(root: &Path, path: &Path) -> io::Result<()> {
+    for ancestor in path.ancestors() {
+        if ancestor == root || !ancestor.starts_with(root) {
+            break;
+        }
+        match std::fs::symlink_metadata(ancestor) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(io::Error::other(format!(
+                    "refusing to follow the symlink {}",
+                    ancestor.display()
+                )));
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(components: &[&str]) -> Vec<String> {
+        components.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn drops_navigating_components() {
+        assert_eq!(
+            relative_path(&path(&["..", "a", ".", "", "..", "b"])),
+            Path::new("a/b")
+        );
+        assert_eq!(relative_path(&path(&["..", ".."])), Path::new("_"));
+    }
+
+    #[test]
+    fn keeps_absolute_paths_relative() {
+        assert_eq!(
+            relative_path(&path(&["/etc", "passwd"])),
+            Path::new("_etc/passwd")
+        );
+        assert_eq!(component("C:\\Windows").as_deref(), Some("C__Windows"));
+    }
+
+    #[test]
+    fn renames_reserved_names() {
+        assert_eq!(component("CON.txt").as_deref(), Some("CON_.txt"));
+        assert_eq!(component("lpt1").as_deref(), Some("lpt1_"));
+        assert_eq!(component("CONSOLE.txt").as_deref(), Some("CONSOLE.txt"));
+        assert_eq!(component("name. ").as_deref(), Some("name"));
+    }
+
+    #[test]
+    fn renames_case_collisions() {
+        let mut sanitizer = PathSanitizer::default();
+        assert_eq!(
+            sanitizer.file_path(&path(&["README.txt"])),
+            Path::new("README.txt")
+        );
+        assert_eq!(
+            sanitizer.file_path(&path(&["readme.txt"])),
+            Path::new("readme_1.txt")
+        );
+        assert_eq!(
+            sanitizer.file_path(&path(&["ReadMe.txt"])),
+            Path::new("ReadMe_2.txt")
+        );
+        assert_eq!(sanitizer.file_path(&path(&["a", "b"])), Path::new("a/b"));
+        assert_eq!(sanitizer.file_path(&path(&["A"])), Path::new("A_1"));
+    }
+
+    #[test]
+    fn renames_directories_colliding_with_files() {
+        let mut sanitizer = PathSanitizer::default();
+        assert_eq!(sanitizer.file_path(&path(&["n", "x"])), Path::new("n/x"));
+        assert_eq!(
+            sanitizer.file_path(&path(&["n", "X", "y"])),
+            Path::new("n/X_1/y")
+        );
+        assert_eq!(
+            sanitizer.file_path(&path(&["n", "x", "z"])),
+            Path::new("n/X_1/z")
+        );
+        assert_eq!(
+            sanitizer.file_path(&path(&["n", "X", "Y"])),
+            Path::new("n/X_1/Y_1")
+        );
+        assert_eq!(sanitizer.file_path(&path(&["N", "w"])), Path::new("n/w"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_below_root() {
+        let root = std::env::temp_dir().join(format!("bittorrust-sanitize-{}", std::process::id()));
+        let outside = root.with_extension("outside");
+        std::fs::create_dir_all(root.join("dir")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+        let checks = (
+            check_no_symlinks(&root, &root.join("dir/file")),
+            check_no_symlinks(&root, &root.join("link/file")),
+            check_no_symlinks(&root, &root.join("link")),
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+        std::fs::remove_dir_all(&outside).unwrap();
+        assert!(checks.0.is_ok());
+        assert!(checks.1.is_err());
+        assert!(checks.2.is_err());
+    }
+}
@@ -0,0 +1,442 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use tokio::{
+    fs::{self, OpenOptions},
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+
+use crate::{
+    sanitize::{self, PathSanitizer},
+    torrent::{Info, MetaVersion},
+};
+
+/// One file of the torrent on disk and the range of the torrent's bytes it holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSpan {
+    pub path: PathBuf,
+    /// Offset of the file's first byte within the torrent's content
+    pub offset: u64,
+    pub length: u64,
+    /// Padding files read as zeros and are never created on disk
+    pub padding: bool,
+    pub attributes: FileAttributes,
+}
+
+impl FileSpan {
+    /// Whether pieces are read from and written to the file, rather than it being padding or
+    /// a symlink
+    pub fn holds_data(&self) -> bool {
+        !self.padding && self.attributes.symlink.is_none()
+    }
+}
+
+/// BEP 47 attributes of a file, applied when it is created
+/// [spec](http://bittorrent.org/beps/bep_0047.html)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileAttributes {
+    pub executable: bool,
+    /// Only set on Windows, elsewhere a file is hidden by its name starting with a dot
+    pub hidden: bool,
+    /// Target of a symlink relative to the directory holding it, the link is created in place
+    /// of the file
+    pub symlink: Option<PathBuf>,
+}
+
+impl FileAttributes {
+    /// Attributes of a file `depth` directories below the torrent's root, with `symlink_path`
+    /// given from that root
+    fn new(attr: Option<&str>, depth: usize, symlink_path: Option<&[String]>) -> FileAttributes {
+        let attr = attr.unwrap_or_default();
+        let symlink = symlink_path.filter(|_| attr.contains('l')).map(|target| {
+            std::iter::repeat_n(Path::new(".."), depth)
+                .collect::<PathBuf>()
+                .join(sanitize::relative_path(target))
+        });
+        FileAttributes {
+            executable: attr.contains('x'),
+            hidden: attr.contains('h'),
+            symlink,
+        }
+    }
+}
+
+/// Part of a file covered by a byte range of the torrent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileSlice<'s> {
+    pub file: &'s FileSpan,
+    /// Offset within the file
+    pub file_offset: u64,
+    /// Offset within the requested range
+    pub range_offset: u64,
+    pub length: u64,
+}
+
+/// Maps the torrent's content, a concatenation of its files, onto the files on disk
+#[derive(Debug, Clone)]
+pub struct Storage {
+    files: Vec<FileSpan>,
+    piece_length: u64,
+    /// Directory the torrent's paths are below, nothing under it is written through a symlink
+    root: PathBuf,
+}
+
+impl Storage {
+    /// Single-file torrents are written to `output` itself, multi-file torrents to
+    /// `output/<name>/<path>` with the names made safe and kept inside `output`
+    pub fn new(info: &Info, output: &Path) -> Storage {
+        let mut sanitizer = PathSanitizer::default();
+        Self::laid_out(info, output, |components| sanitizer.file_path(components))
+    }
+    /// The files under the paths given in the torrent, as is, relative to an empty root, e.g.
+    /// for their URLs on a web seed
+    pub fn unsanitized(info: &Info) -> Storage {
+        Self::laid_out(info, Path::new(""), |components| {
+            components.iter().collect()
+        })
+    }
+    /// Layout with `file_path` turning `[name, path...]` of each file of a multi-file torrent
+    /// into its path relative to `output`
+    fn laid_out(
+        info: &Info,
+        output: &Path,
+        file_path: impl FnMut(&[String]) -> PathBuf,
+    ) -> Storage {
+        let files = if info.version() == MetaVersion::V2 {
+            Self::from_file_tree(info, output, file_path)
+        } else {
+            Self::from_files(info, output, file_path)
+        };
+        // a single file is `output` itself, which the user chose
+        let root = match &files[..] {
+            [file] if file.path == output => output.parent().unwrap_or(Path::new("")),
+            _ => output,
+        };
+        Storage {
+            files,
+            piece_length: info.piece_length as u64,
+            root: root.to_path_buf(),
+        }
+    }
+    fn from_files(
+        info: &Info,
+        output: &Path,
+        mut file_path: impl FnMut(&[String]) -> PathBuf,
+    ) -> Vec<FileSpan> {
+        let Some(files) = &info.files else {
+            return vec![FileSpan {
+                path: output.to_path_buf(),
+                offset: 0,
+                length: info.length.unwrap_or_default() as u64,
+                padding: false,
+                attributes: FileAttributes::default(),
+            }];
+        };
+        let mut offset = 0;
+        files
+            .iter()
+            .map(|file| {
+                let padding = file.is_padding();
+                // padding is never on disk, so it takes no name from the real files
+                let (path, depth) = if padding {
+                    (PathBuf::new(), 0)
+                } else {
+                    let path = file_path(&[std::slice::from_ref(&info.name), &file.path].concat());
+                    let depth = path.components().count().saturating_sub(2);
+                    (output.join(path), depth)
+                };
+                let span = FileSpan {
+                    path,
+                    offset,
+                    length: file.length as u64,
+                    padding,
+                    attributes: FileAttributes::new(
+                        file.attr.as_deref(),
+                        depth,
+                        file.symlink_path.as_deref(),
+                    ),
+                };
+                // `Info::validate` checks that the offsets fit, this only keeps an unvalidated
+                // `Info` from panicking
+                offset = offset.saturating_add(span.length);
+                span
+            })
+            .collect()
+    }
+    /// Layout of a v2-only torrent, every file starts on a piece boundary so the gaps
+    /// between them are padding
+    fn from_file_tree(
+        info: &Info,
+        output: &Path,
+        mut file_path: impl FnMut(&[String]) -> PathBuf,
+    ) -> Vec<FileSpan> {
+        let tree = info.file_tree.as_deref().unwrap_or_default();
+        let piece_length = info.piece_length as u64;
+        let single_file = matches!(tree, [file] if file.path == [info.name.clone()]);
+        let mut files = vec![];
+        let mut offset: u64 = 0;
+        for file in tree {
+            if !offset.is_multiple_of(piece_length) {
+                let padding = piece_length - offset % piece_length;
+                files.push(FileSpan {
+                    path: PathBuf::new(),
+                    offset,
+                    length: padding,
+                    padding: true,
+                    attributes: FileAttributes::default(),
+                });
+                offset = offset.saturating_add(padding);
+            }
+            let (path, depth) = if single_file {
+                (output.to_path_buf(), 0)
+            } else {
+                let path = file_path(&[std::slice::from_ref(&info.name), &file.path].concat());
+                let depth = path.components().count().saturating_sub(2);
+                (output.join(path), depth)
+            };
+            files.push(FileSpan {
+                path,
+                offset,
+                length: file.length,
+                padding: false,
+                attributes: FileAttributes::new(
+                    file.attr.as_deref(),
+                    depth,
+                    file.symlink_path.as_deref(),
+                ),
+            });
+            offset = offset.saturating_add(file.length);
+        }
+        files
+    }
+    /// Storage over files already laid out end to end, e.g. when creating a torrent
+    pub fn from_spans(files: Vec<FileSpan>, piece_length: u32) -> Storage {
+        Storage {
+            files,
+            piece_length: piece_length as u64,
+            root: PathBuf::new(),
+        }
+    }
+    pub fn files(&self) -> &[FileSpan] {
+        &self.files
+    }
+    pub fn total_length(&self) -> u64 {
+        self.files
+            .iter()
+            .fold(0, |total, file| total.saturating_add(file.length))
+    }
+    /// Creates the directory tree and every file at its final size, so files that no piece
+    /// touches (zero-length ones) exist too, along with the files' symlinks and attributes
+    pub async fn allocate(&self) -> std::io::Result<()> {
+        for file in self.files.iter().filter(|file| !file.padding) {
+            if let Some(parent) = file.path.parent() {
+                sanitize::check_no_symlinks(&self.root, parent)?;
+                fs::create_dir_all(parent).await?;
+            }
+            if let Some(target) = &file.attributes.symlink {
+                if fs::symlink_metadata(&file.path).await.is_ok() {
+                    fs::remove_file(&file.path).await?;
+                }
+                #[cfg(unix)]
+                fs::symlink(target, &file.path).await?;
+                #[cfg(windows)]
+                fs::symlink_file(target, &file.path).await?;
+                continue;
+            }
+            sanitize::check_no_symlinks(&self.root, &file.path)?;
+            let mut options = OpenOptions::new();
+            options.write(true).create(true).truncate(false);
+            #[cfg(windows)]
+            if file.attributes.hidden {
+                // FILE_ATTRIBUTE_HIDDEN, only applied when the file is created
+                options.attributes(0x2);
+            }
+            let handle = options.open(&file.path).await?;
+            handle.set_len(file.length).await?;
+            #[cfg(unix)]
+            if file.attributes.executable {
+                use std::os::unix::fs::PermissionsExt;
+                let mut permissions = handle.metadata().await?.permissions();
+                // executable by whoever may read it
+                permissions.set_mode(permissions.mode() | (permissions.mode() & 0o444) >> 2);
+                handle.set_permissions(permissions).await?;
+            }
+        }
+        Ok(())
+    }
+    /// Files covered by `length` bytes of the torrent starting at `offset`, in order
+    pub fn slices(&self, offset: u64, length: u64) -> Vec<FileSlice<'_>> {
+        let end = offset + length;
+        self.files
+            .iter()
+            .filter(|file| file.length > 0)
+            .filter(|file| file.offset < end && offset < file.offset + file.length)
+            .map(|file| {
+                let start = offset.max(file.offset);
+                let stop = end.min(file.offset + file.length);
+                FileSlice {
+                    file,
+                    file_offset: start - file.offset,
+                    range_offset: start - offset,
+                    length: stop - start,
+                }
+            })
+            .collect()
+    }
+    /// Reads `length` bytes of the torrent starting at `offset`, blocking the calling thread,
+    /// for hashing on worker threads
+    pub fn read_range(&self, offset: u64, length: u64) -> std::io::Result<Vec<u8>> {
+        let mut data = vec![0; length as usize];
+        for slice in self.slices(offset, length) {
+            if !slice.file.holds_data() {
+                continue;
+            }
+            let mut file = std::fs::File::open(&slice.file.path)?;
+            file.seek(SeekFrom::Start(slice.file_offset))?;
+            let start = slice.range_offset as usize;
+            file.read_exact(&mut data[start..start + slice.length as usize])?;
+        }
+        Ok(data)
+    }
+    /// Writes a verified piece, splitting it across the files it spans
+    pub async fn write_piece(&self, piece_index: u32, data: &[u8]) -> std::io::Result<()> {
+        let offset = piece_index as u64 * self.piece_length;
+        for slice in self.slices(offset, data.len() as u64) {
+            if !slice.file.holds_data() {
+                continue;
+            }
+            sanitize::check_no_symlinks(&self.root, &slice.file.path)?;
+            let mut file = OpenOptions::new()
+                .write(true)
+                .open(&slice.file.path)
+                .await?;
+            file.seek(SeekFrom::Start(slice.file_offset)).await?;
+            let start = slice.range_offset as usize;
+            file.write_all(&data[start..start + slice.length as usize])
+                .await?;
+            // tokio finishes writes in the background; wait so reads see the piece
+            file.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bencode_parser::Value, test_util::TempDir, torrent::Torrent};
+
+    #[test]
+    fn keeps_symlink_targets_inside_the_root() {
+        let target: Vec<String> = ["..", "..", "/etc", "passwd"].map(String::from).to_vec();
+        let attributes = FileAttributes::new(Some("l"), 1, Some(&target));
+        assert_eq!(
+            attributes.symlink.as_deref(),
+            Some(Path::new("../_etc/passwd"))
+        );
+        let attributes = FileAttributes::new(Some("x"), 0, Some(&target));
+        assert_eq!(attributes.symlink, None);
+    }
+
+    /// `t/a`, padding up to the next piece, the executable `t/sub/run.sh` and the symlink
+    /// `t/sub/link` to `t/a`
+    fn padded_torrent() -> Torrent {
+        let file = |path: &[&str], length: i64, attr: Option<&str>| -> Value {
+            let mut entry = vec![
+                ("length", Value::from(length)),
+                (
+                    "path",
+                    path.iter()
+                        .map(|&c| Value::from(c))
+                        .collect::<Vec<_>>()
+                        .into(),
+                ),
+            ];
+            if let Some(attr) = attr {
+                entry.push(("attr", Value::from(attr)));
+            }
+            if attr == Some("l") {
+                entry.push(("symlink path", vec![Value::from("a")].into()));
+            }
+            entry.into_iter().collect()
+        };
+        let files = vec![
+            file(&["a"], 100, None),
+            file(&[".pad", "16284"], 16284, Some("p")),
+            file(&["sub", "run.sh"], 10, Some("x")),
+            file(&["sub", "link"], 0, Some("l")),
+        ];
+        let info: Value = [
+            ("files", Value::from(files)),
+            ("name", Value::from("t")),
+            ("piece length", Value::from(16384)),
+            ("pieces", Value::from(vec![0; 40])),
+        ]
+        .into_iter()
+        .collect();
+        let metainfo: Value = [("info", info)].into_iter().collect();
+        Torrent::from_bytes(&metainfo.encode()).unwrap()
+    }
+
+    #[test]
+    fn lays_out_padding_and_attributes() {
+        let torrent = padded_torrent();
+        let storage = Storage::new(&torrent.info, Path::new("out"));
+        let files = storage.files();
+        let layout: Vec<_> = files
+            .iter()
+            .map(|file| (file.path.to_str().unwrap(), file.offset, file.holds_data()))
+            .collect();
+        assert_eq!(
+            layout,
+            [
+                ("out/t/a", 0, true),
+                ("", 100, false),
+                ("out/t/sub/run.sh", 16384, true),
+                ("out/t/sub/link", 16394, false)
+            ]
+        );
+        assert!(files[1].padding);
+        assert!(files[2].attributes.executable);
+        assert_eq!(
+            files[3].attributes.symlink.as_deref(),
+            Some(Path::new("../a"))
+        );
+        assert_eq!(storage.total_length(), 16394);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn writes_around_padding_and_applies_attributes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let torrent = padded_torrent();
+        let dir = TempDir::new("storage-padding");
+        let storage = Storage::new(&torrent.info, &dir.0);
+        storage.allocate().await.unwrap();
+        // padding is never written, whatever the piece holds there
+        let mut piece = vec![7; 100];
+        piece.resize(16384, 1);
+        storage.write_piece(0, &piece).await.unwrap();
+        storage.write_piece(1, &[3; 10]).await.unwrap();
+
+        let mut expected = vec![7; 100];
+        expected.resize(16384, 0);
+        expected.extend([3; 10]);
+        assert_eq!(storage.read_range(0, 16394).unwrap(), expected);
+        let root = dir.0.join("t");
+        assert!(!root.join(".pad").exists());
+        assert_eq!(std::fs::read(root.join("a")).unwrap(), [7; 100]);
+        let mode = std::fs::metadata(root.join("sub/run.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_ne!(mode & 0o111, 0);
+        assert_eq!(
+            std::fs::read_link(root.join("sub/link")).unwrap(),
+            Path::new("../a")
+        );
+    }
+}
@@ -0,0 +1,28 @@
+use std::{fs, path::PathBuf};
+
+/// A fresh directory under the system temp directory, removed when dropped
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    /// `name` tells apart the directories of tests running at the same time
+    pub fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("bittorrust-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+    /// Writes `length` bytes of a repeating pattern to `path` below the directory
+    pub fn write(&self, path: &str, length: usize) -> PathBuf {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let data: Vec<u8> = (0..length).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, data).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
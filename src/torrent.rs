@@ -1,51 +1,446 @@
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    io::Read,
+    path::PathBuf,
+};
 
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
 use tokio::{fs::File, io::AsyncReadExt};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+use crate::{
+    bencode_parser::{self, borrowed, DecodeError, Value},
+    merkle::{self, Hash},
+};
+
+/// Why a metainfo file could not be loaded
+#[derive(Debug)]
+pub enum MetainfoError {
+    /// The file could not be opened or read
+    Open {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Reading from the reader failed
+    Io(std::io::Error),
+    /// The input is not valid bencode
+    Decode(DecodeError),
+    /// A required key is absent, `field` is the dotted path to it, e.g. `info.files[2].length`
+    MissingField { field: String },
+    /// A dictionary has the same key more than once, which readers may resolve differently
+    DuplicateKey { field: String },
+    /// A key holds the wrong kind of value
+    WrongType {
+        field: String,
+        expected: &'static str,
+    },
+    /// An integer does not fit the field
+    OutOfRange { field: String, value: i64 },
+    /// `piece length` is zero or negative
+    InvalidPieceLength(i64),
+    /// `pieces` is not a whole number of 20-byte SHA-1 hashes
+    PiecesNotMultipleOf20 { length: usize },
+    /// The number of piece hashes does not cover the total length
+    PieceCountMismatch { expected: u64, actual: usize },
+    /// `info` has both `length` and `files`
+    BothLengthAndFiles,
+    /// `info` has neither `length` nor `files`
+    NoLengthOrFiles,
+    /// `files` or `file tree` lists no files
+    EmptyFileList,
+    /// The files add up to more bytes than fit in 64 bits, or to more pieces than 32-bit
+    /// piece indexes can count
+    TooLong,
+    /// `meta version` is not 2, the only version after the implicit v1
+    UnsupportedMetaVersion(i64),
+    /// v2 pieces are merkle subtrees, so `piece length` must be a power of two of 16 KiB or more
+    PieceLengthNotPowerOfTwo(u32),
+    /// A file longer than one piece has no entry in `piece layers`
+    MissingPieceLayer { path: String },
+    /// A `piece layers` entry has the wrong length or does not hash to the file's `pieces root`
+    InvalidPieceLayer { path: String },
+    /// The v1 `files` of a hybrid torrent disagree with its v2 `file tree`
+    HybridMismatch { path: String },
+}
+
+impl MetainfoError {
+    /// Prefixes the field path with the dictionary the error was found in
+    fn within(self, parent: &str) -> Self {
+        match self {
+            MetainfoError::MissingField { field } => MetainfoError::MissingField {
+                field: format!("{parent}.{field}"),
+            },
+            MetainfoError::DuplicateKey { field } => MetainfoError::DuplicateKey {
+                field: format!("{parent}.{field}"),
+            },
+            MetainfoError::WrongType { field, expected } => MetainfoError::WrongType {
+                field: format!("{parent}.{field}"),
+                expected,
+            },
+            MetainfoError::OutOfRange { field, value } => MetainfoError::OutOfRange {
+                field: format!("{parent}.{field}"),
+                value,
+            },
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for MetainfoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetainfoError::Open { path, source } => {
+                write!(f, "failed to read {}: {source}", path.display())
+            }
+            MetainfoError::Io(err) => write!(f, "failed to read metainfo: {err}"),
+            MetainfoError::Decode(err) => write!(f, "invalid metainfo {err}"),
+            MetainfoError::MissingField { field } => write!(f, "missing `{field}`"),
+            MetainfoError::DuplicateKey { field } => write!(f, "`{field}` appears more than once"),
+            MetainfoError::WrongType { field, expected } => {
+                write!(f, "`{field}` should be {expected}")
+            }
+            MetainfoError::OutOfRange { field, value } => {
+                write!(f, "`{field}` is out of range: {value}")
+            }
+            MetainfoError::InvalidPieceLength(length) => {
+                write!(f, "`info.piece length` should be positive, found {length}")
+            }
+            MetainfoError::PiecesNotMultipleOf20 { length } => {
+                write!(f, "`info.pieces` is {length} bytes, not a multiple of 20")
+            }
+            MetainfoError::PieceCountMismatch { expected, actual } => write!(
+                f,
+                "`info.pieces` has {actual} hashes, the total length needs {expected}"
+            ),
+            MetainfoError::BothLengthAndFiles => {
+                f.write_str("`info` has both `length` and `files`")
+            }
+            MetainfoError::NoLengthOrFiles => {
+                f.write_str("`info` has neither `length` nor `files`")
+            }
+            MetainfoError::EmptyFileList => f.write_str("torrent has no files"),
+            MetainfoError::TooLong => f.write_str("torrent is too long to be addressed"),
+            MetainfoError::UnsupportedMetaVersion(version) => {
+                write!(f, "unsupported `info.meta version` {version}")
+            }
+            MetainfoError::PieceLengthNotPowerOfTwo(length) => write!(
+                f,
+                "`info.piece length` should be a power of two of at least 16 KiB for v2, found {length}"
+            ),
+            MetainfoError::MissingPieceLayer { path } => {
+                write!(f, "`piece layers` has no entry for `{path}`")
+            }
+            MetainfoError::InvalidPieceLayer { path } => write!(
+                f,
+                "`piece layers` entry for `{path}` does not match its `pieces root`"
+            ),
+            MetainfoError::HybridMismatch { path } => write!(
+                f,
+                "v1 `info.files` and v2 `info.file tree` of the hybrid torrent disagree at `{path}`"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MetainfoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MetainfoError::Open { source, .. } => Some(source),
+            MetainfoError::Io(err) => Some(err),
+            MetainfoError::Decode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for MetainfoError {
+    fn from(err: DecodeError) -> Self {
+        MetainfoError::Decode(err)
+    }
+}
+
+impl From<std::io::Error> for MetainfoError {
+    fn from(err: std::io::Error) -> Self {
+        MetainfoError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Torrent {
-    /// URL of the tracker
-    pub announce: String,
+    /// URL of the tracker, absent for trackerless torrents and those with only an
+    /// `announce-list` or web seeds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub announce: Option<String>,
     /// Information about the file(s) being shared
     pub info: Info,
     /// List of lists of URLs
     /// If available, then `announce` is ignored
     /// [spec](http://bittorrent.org/beps/bep_0012.html)
-    #[serde(default)]
     #[serde(rename = "announce-list")]
     pub announce_list: Option<Vec<Vec<String>>>,
     /// Comment about the torrent by the creator
-    #[serde(default)]
     pub comment: Option<String>,
     /// Name and version of the program used to create the .torrent
-    #[serde(default)]
     #[serde(rename = "created by")]
     pub created_by: Option<String>,
     /// Creation date of the torrent
-    #[serde(default)]
     #[serde(rename = "creation date")]
     pub creation_date: Option<u64>,
     /// Encoding used for the fields in the torrent
-    #[serde(default)]
     pub encoding: Option<String>,
+    /// Web seeds, HTTP servers holding the files under the torrent's names
+    /// [spec](http://bittorrent.org/beps/bep_0019.html)
+    #[serde(rename = "url-list")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub url_list: Vec<String>,
+    /// HTTP seeds, scripts serving whole pieces by index
+    /// [spec](http://bittorrent.org/beps/bep_0017.html)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub httpseeds: Vec<String>,
+    /// The `info` dictionary exactly as it appeared in the metainfo file,
+    /// including keys that `Info` does not model
+    #[serde(skip)]
+    pub raw_info: Vec<u8>,
+    /// v2: piece layer of every file longer than one piece, keyed by its `pieces root`
+    #[serde(skip)]
+    pub piece_layers: BTreeMap<Hash, Vec<u8>>,
 }
 
 impl Torrent {
-    pub async fn new(file: PathBuf) -> Torrent {
-        let file = File::open(file).await.unwrap();
-        let mut buf_reader = tokio::io::BufReader::new(file);
+    pub async fn new(file: PathBuf) -> Result<Torrent, MetainfoError> {
+        let read = async {
+            let file = File::open(&file).await?;
+            let mut buf_reader = tokio::io::BufReader::new(file);
+            let mut bytes = Vec::new();
+            buf_reader.read_to_end(&mut bytes).await?;
+            Ok(bytes)
+        };
+        let bytes = read
+            .await
+            .map_err(|source| MetainfoError::Open { path: file, source })?;
+        Torrent::from_bytes(&bytes)
+    }
+    pub fn from_reader(mut reader: impl Read) -> Result<Torrent, MetainfoError> {
         let mut bytes = Vec::new();
-        buf_reader.read_to_end(&mut bytes).await.unwrap();
-        serde_bencode::from_bytes::<Torrent>(&bytes).unwrap()
+        reader.read_to_end(&mut bytes)?;
+        Torrent::from_bytes(&bytes)
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Torrent, MetainfoError> {
+        // locate the info dict first so malformed bencode is reported with its offset
+        let info_span = bencode_parser::dict_value_span(bytes, b"info")?.ok_or(
+            MetainfoError::MissingField {
+                field: "info".to_string(),
+            },
+        )?;
+        let (metainfo, _) = bencode_parser::decode_borrowed(bytes)?;
+        Torrent::from_value(&metainfo, bytes[info_span].to_vec())
+    }
+    /// Builds and validates the torrent from a decoded metainfo dictionary and the bytes
+    /// its `info` was decoded from
+    fn from_value(metainfo: &borrowed::Value, raw_info: Vec<u8>) -> Result<Torrent, MetainfoError> {
+        if metainfo.as_dict().is_none() {
+            return Err(MetainfoError::WrongType {
+                field: "metainfo".to_string(),
+                expected: "a dictionary",
+            });
+        }
+        // the info hash covers the raw bytes while lookups find the last of repeated keys, at
+        // any depth
+        unique_keys(metainfo)?;
+        let announce_list = match metainfo.get("announce-list") {
+            Some(tiers) => Some(
+                as_list(tiers, "announce-list")?
+                    .iter()
+                    .enumerate()
+                    .map(|(index, tier)| string_list(tier, &format!("announce-list[{index}]")))
+                    .collect::<Result<_, _>>()?,
+            ),
+            None => None,
+        };
+        let info =
+            Info::from_value(required(metainfo, "info")?).map_err(|err| err.within("info"))?;
+        info.validate()?;
+        let piece_layers = match metainfo.get("piece layers") {
+            Some(layers) => parse_piece_layers(layers)?,
+            None => BTreeMap::new(),
+        };
+        let torrent = Torrent {
+            announce: optional_str(metainfo, "announce")?,
+            info,
+            announce_list,
+            comment: optional_str(metainfo, "comment")?,
+            created_by: optional_str(metainfo, "created by")?,
+            creation_date: optional_int(metainfo, "creation date")?
+                .map(|date| {
+                    u64::try_from(date).map_err(|_| MetainfoError::OutOfRange {
+                        field: "creation date".to_string(),
+                        value: date,
+                    })
+                })
+                .transpose()?,
+            encoding: optional_str(metainfo, "encoding")?,
+            // a single web seed may be given as a plain string
+            url_list: match metainfo.get("url-list") {
+                Some(url) if url.as_bytes().is_some() => vec![as_string(url, "url-list")?],
+                Some(urls) => string_list(urls, "url-list")?,
+                None => vec![],
+            },
+            httpseeds: match metainfo.get("httpseeds") {
+                Some(urls) => string_list(urls, "httpseeds")?,
+                None => vec![],
+            },
+            raw_info,
+            piece_layers,
+        };
+        if torrent.info.version() != MetaVersion::V1 {
+            torrent.validate_piece_layers()?;
+        }
+        Ok(torrent)
+    }
+    /// Checks that every file longer than a piece has a piece layer hashing to its root
+    pub fn validate_piece_layers(&self) -> Result<(), MetainfoError> {
+        let piece_length = self.info.piece_length;
+        for file in self.info.file_tree.iter().flatten() {
+            let Some(root) = file
+                .pieces_root
+                .filter(|_| file.length > piece_length as u64)
+            else {
+                continue;
+            };
+            let path = file.path.join("/");
+            let layer = self
+                .piece_layers
+                .get(&root)
+                .ok_or_else(|| MetainfoError::MissingPieceLayer { path: path.clone() })?;
+            let expected = file.length.div_ceil(piece_length as u64) as usize * 32;
+            let hashes: Vec<Hash> = layer
+                .chunks_exact(32)
+                .map(|hash| hash.try_into().unwrap())
+                .collect();
+            if layer.len() != expected || merkle::file_root(&hashes, piece_length) != root {
+                return Err(MetainfoError::InvalidPieceLayer { path });
+            }
+        }
+        Ok(())
+    }
+    /// Torrent for a bare `info` dictionary, as fetched from peers for a magnet link,
+    /// announcing to `trackers` with each one in its own tier. v2 and hybrid torrents still
+    /// need their `piece_layers` before they are complete.
+    pub fn from_info_bytes(info: &[u8], trackers: &[String]) -> Result<Torrent, MetainfoError> {
+        let (value, _) = bencode_parser::decode_borrowed(info)?;
+        let info_dict = Info::from_value(&value).map_err(|err| err.within("info"))?;
+        info_dict.validate()?;
+        Ok(Torrent {
+            announce: trackers.first().cloned(),
+            info: info_dict,
+            announce_list: (!trackers.is_empty()).then(|| {
+                trackers
+                    .iter()
+                    .map(|tracker| vec![tracker.clone()])
+                    .collect()
+            }),
+            comment: None,
+            created_by: None,
+            creation_date: None,
+            encoding: None,
+            url_list: vec![],
+            httpseeds: vec![],
+            raw_info: info.to_vec(),
+            piece_layers: BTreeMap::new(),
+        })
+    }
+    /// Trackers by tier, `announce` alone when there is no `announce-list`
+    pub fn trackers(&self) -> Vec<Vec<String>> {
+        match &self.announce_list {
+            Some(tiers) if !tiers.is_empty() => tiers.clone(),
+            _ => self.announce.iter().map(|url| vec![url.clone()]).collect(),
+        }
     }
+    /// The tracker to announce to first, `None` for trackerless torrents
+    pub fn tracker(&self) -> Option<&String> {
+        match &self.announce_list {
+            Some(tiers) if !tiers.is_empty() => tiers.iter().flatten().next(),
+            _ => self.announce.as_ref(),
+        }
+    }
+    /// Bencoded `info` dictionary, exactly as it was loaded
+    pub fn info_bytes(&self) -> &[u8] {
+        &self.raw_info
+    }
+    /// v1 info hash, the SHA-1 of the `info` dictionary
     pub fn info_hash(&self) -> [u8; 20] {
-        let bencoded_info = serde_bencode::to_bytes(&self.info).unwrap();
-        let info_hash = Sha1::digest(bencoded_info.clone());
+        let info_hash = Sha1::digest(self.info_bytes());
         info_hash.into()
     }
+    /// v2 info hash, the SHA-256 of the `info` dictionary, for v2 and hybrid torrents
+    pub fn info_hash_v2(&self) -> Option<Hash> {
+        (self.info.version() != MetaVersion::V1).then(|| Sha256::digest(self.info_bytes()).into())
+    }
+    /// 20-byte hash the swarm is known by to trackers and in handshakes: the v1 info hash,
+    /// or the v2 info hash truncated to 20 bytes for v2-only torrents
+    pub fn swarm_hash(&self) -> [u8; 20] {
+        match self.info.version() {
+            MetaVersion::V2 => self.info_hash_v2().unwrap()[..20].try_into().unwrap(),
+            _ => self.info_hash(),
+        }
+    }
+    /// Bencoded metainfo, with the `info` dictionary written back byte for byte
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let bencoded = serde_bencode::to_bytes(self).unwrap();
+        let (decoded, _) = bencode_parser::decode_bytes(&bencoded).unwrap();
+        let Value::Dict(mut dict) = decoded else {
+            unreachable!("torrent serializes to a dictionary")
+        };
+        if !self.piece_layers.is_empty() {
+            let layers = self
+                .piece_layers
+                .iter()
+                .map(|(root, layer)| (root.to_vec(), Value::from(layer.as_slice())))
+                .collect();
+            dict.insert(b"piece layers".to_vec(), Value::Dict(layers));
+        }
+        let mut out = vec![b'd'];
+        for (key, value) in dict {
+            bencode_parser::encode_byte_string(&key, &mut out);
+            if key == b"info" {
+                out.extend(self.info_bytes());
+            } else {
+                value.encode_to(&mut out);
+            }
+        }
+        out.push(b'e');
+        out
+    }
+    /// Whether `data` is piece `index`, by its SHA-1 hash, or for v2-only torrents by the
+    /// file's piece layer
+    pub fn verify_piece(&self, index: u32, data: &[u8]) -> bool {
+        if self.info.version() != MetaVersion::V2 {
+            let start = index as usize * 20;
+            return self
+                .info
+                .pieces
+                .get(start..start + 20)
+                .is_some_and(|hash| *hash == *Sha1::digest(data));
+        }
+        let piece_length = self.info.piece_length;
+        let Some(piece) = self.info.v2_piece(index) else {
+            return false;
+        };
+        let Some(root) = piece.file.pieces_root else {
+            return false;
+        };
+        if piece.file.length <= piece_length as u64 {
+            return merkle::small_file_root(data) == root;
+        }
+        let start = piece.index as usize * 32;
+        self.piece_layers
+            .get(&root)
+            .and_then(|layer| layer.get(start..start + 32))
+            .is_some_and(|hash| *hash == merkle::piece_hash(data, piece_length))
+    }
     pub fn get_piece_hashes(&self) -> Vec<String> {
         let mut piece_hashes = Vec::new();
         for hash in self.info.pieces.chunks(20) {
@@ -55,6 +450,556 @@ impl Torrent {
     }
 }
 
+/// Which hash schemes a torrent's `info` dictionary carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaVersion {
+    /// SHA-1 `pieces` over the concatenated files
+    V1,
+    /// Per-file SHA-256 merkle trees in `file tree`
+    /// [spec](http://bittorrent.org/beps/bep_0052.html)
+    V2,
+    /// Both, describing the same content so v1 and v2 peers can share one swarm
+    Hybrid,
+}
+
+impl fmt::Display for MetaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MetaVersion::V1 => "v1",
+            MetaVersion::V2 => "v2",
+            MetaVersion::Hybrid => "hybrid",
+        })
+    }
+}
+
+impl Info {
+    pub fn from_value(info: &borrowed::Value) -> Result<Info, MetainfoError> {
+        unique_keys(info)?;
+        let files = match info.get("files") {
+            Some(files) => Some(
+                as_list(files, "files")?
+                    .iter()
+                    .enumerate()
+                    .map(|(index, file)| {
+                        TorrentFile::from_value(file)
+                            .map_err(|err| err.within(&format!("files[{index}]")))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            None => None,
+        };
+        let piece_length = required_int(info, "piece length")?;
+        if piece_length <= 0 {
+            return Err(MetainfoError::InvalidPieceLength(piece_length));
+        }
+        let meta_version = optional_int(info, "meta version")?;
+        let file_tree = match meta_version {
+            None => None,
+            Some(2) => {
+                let mut files = vec![];
+                parse_file_tree(required(info, "file tree")?, &mut vec![], &mut files)?;
+                Some(files)
+            }
+            Some(version) => return Err(MetainfoError::UnsupportedMetaVersion(version)),
+        };
+        // v2-only torrents have no `pieces`, their hashes live in the file tree
+        let pieces = match (info.get("pieces"), meta_version) {
+            (None, Some(_)) => &[][..],
+            (pieces, _) => pieces
+                .ok_or_else(|| MetainfoError::MissingField {
+                    field: "pieces".to_string(),
+                })?
+                .as_bytes()
+                .ok_or_else(|| wrong_type("pieces", "a byte string"))?,
+        };
+        Ok(Info {
+            name: name_str(info, "name")?,
+            piece_length: u32::try_from(piece_length).map_err(|_| MetainfoError::OutOfRange {
+                field: "piece length".to_string(),
+                value: piece_length,
+            })?,
+            pieces: ByteBuf::from(pieces),
+            private: optional_int(info, "private")?
+                .map(|private| {
+                    u8::try_from(private).map_err(|_| MetainfoError::OutOfRange {
+                        field: "private".to_string(),
+                        value: private,
+                    })
+                })
+                .transpose()?,
+            source: optional_str(info, "source")?,
+            md5sum: optional_str(info, "md5sum")?,
+            sha1: sha1(info)?,
+            length: optional_int(info, "length")?
+                .map(|length| {
+                    usize::try_from(length).map_err(|_| MetainfoError::OutOfRange {
+                        field: "length".to_string(),
+                        value: length,
+                    })
+                })
+                .transpose()?,
+            files,
+            meta_version,
+            file_tree,
+        })
+    }
+    /// Peers only ever come from trackers here, there is no DHT, PEX or local discovery to
+    /// turn off, so private torrents need no special handling when downloading
+    pub fn is_private(&self) -> bool {
+        self.private == Some(1)
+    }
+    pub fn version(&self) -> MetaVersion {
+        let has_v1 = self.length.is_some() || self.files.is_some() || !self.pieces.is_empty();
+        match (self.meta_version, has_v1) {
+            (None, _) => MetaVersion::V1,
+            (Some(_), false) => MetaVersion::V2,
+            (Some(_), true) => MetaVersion::Hybrid,
+        }
+    }
+    /// Checks that the file layout and the piece hashes agree with each other
+    pub fn validate(&self) -> Result<(), MetainfoError> {
+        if self.piece_length == 0 {
+            return Err(MetainfoError::InvalidPieceLength(0));
+        }
+        self.validate_length()?;
+        let version = self.version();
+        if version != MetaVersion::V1 {
+            self.validate_v2()?;
+        }
+        if version != MetaVersion::V2 {
+            self.validate_v1()?;
+        }
+        if version == MetaVersion::Hybrid {
+            self.validate_hybrid()?;
+        }
+        Ok(())
+    }
+    /// Every offset into the content, with v2 files starting on piece boundaries, must fit
+    /// in a `u64`, and every piece index in a `u32`
+    fn validate_length(&self) -> Result<(), MetainfoError> {
+        let piece_length = self.piece_length as u64;
+        let v1_length = self
+            .files
+            .iter()
+            .flatten()
+            .try_fold(0u64, |offset, file| offset.checked_add(file.length as u64))
+            .ok_or(MetainfoError::TooLong)?;
+        let v2_length = self
+            .file_tree
+            .iter()
+            .flatten()
+            .try_fold(0u64, |offset, file| {
+                offset
+                    .checked_next_multiple_of(piece_length)?
+                    .checked_add(file.length)
+            })
+            .ok_or(MetainfoError::TooLong)?;
+        let length = v1_length
+            .max(v2_length)
+            .max(self.length.unwrap_or_default() as u64);
+        if length.div_ceil(piece_length) > u32::MAX as u64 {
+            return Err(MetainfoError::TooLong);
+        }
+        Ok(())
+    }
+    fn validate_v1(&self) -> Result<(), MetainfoError> {
+        match (&self.length, &self.files) {
+            (Some(_), Some(_)) => return Err(MetainfoError::BothLengthAndFiles),
+            (None, None) => return Err(MetainfoError::NoLengthOrFiles),
+            (None, Some(files)) if files.is_empty() => return Err(MetainfoError::EmptyFileList),
+            _ => {}
+        }
+        if !self.pieces.len().is_multiple_of(20) {
+            return Err(MetainfoError::PiecesNotMultipleOf20 {
+                length: self.pieces.len(),
+            });
+        }
+        let expected = self.total_length().div_ceil(self.piece_length as u64);
+        if expected != self.piece_count() as u64 {
+            return Err(MetainfoError::PieceCountMismatch {
+                expected,
+                actual: self.piece_count(),
+            });
+        }
+        Ok(())
+    }
+    fn validate_v2(&self) -> Result<(), MetainfoError> {
+        if self.piece_length < merkle::BLOCK_LENGTH as u32 || !self.piece_length.is_power_of_two() {
+            return Err(MetainfoError::PieceLengthNotPowerOfTwo(self.piece_length));
+        }
+        if self.file_tree.as_ref().is_none_or(Vec::is_empty) {
+            return Err(MetainfoError::EmptyFileList);
+        }
+        Ok(())
+    }
+    /// Every v1 file other than padding must be the v2 file at the same position, starting
+    /// on a piece boundary
+    fn validate_hybrid(&self) -> Result<(), MetainfoError> {
+        let v2_files = self.file_tree.as_deref().unwrap_or_default();
+        let v1_files: Vec<(Vec<String>, u64, u64)> = match &self.files {
+            Some(files) => {
+                let mut offset = 0;
+                let mut v1_files = vec![];
+                for file in files {
+                    if !file.is_padding() {
+                        v1_files.push((file.path.clone(), file.length as u64, offset));
+                    }
+                    offset += file.length as u64;
+                }
+                v1_files
+            }
+            None => vec![(
+                vec![self.name.clone()],
+                self.length.unwrap_or_default() as u64,
+                0,
+            )],
+        };
+        for (index, v2_file) in v2_files.iter().enumerate() {
+            let mismatch = || MetainfoError::HybridMismatch {
+                path: v2_file.path.join("/"),
+            };
+            let (path, length, offset) = v1_files.get(index).ok_or_else(mismatch)?;
+            if *path != v2_file.path
+                || *length != v2_file.length
+                || offset % self.piece_length as u64 != 0
+            {
+                return Err(mismatch());
+            }
+        }
+        if let Some((path, ..)) = v1_files.get(v2_files.len()) {
+            return Err(MetainfoError::HybridMismatch {
+                path: path.join("/"),
+            });
+        }
+        Ok(())
+    }
+    /// Size of the content, the single file's length or the sum of all files, which
+    /// `validate` checks to fit in a `u64`
+    pub fn total_length(&self) -> u64 {
+        match (&self.files, &self.file_tree) {
+            (Some(files), _) => files
+                .iter()
+                .fold(0, |total, file| total.saturating_add(file.length as u64)),
+            (None, Some(tree)) if self.length.is_none() => tree
+                .iter()
+                .fold(0, |total, file| total.saturating_add(file.length)),
+            _ => self.length.unwrap_or_default() as u64,
+        }
+    }
+    pub fn piece_count(&self) -> usize {
+        match self.version() {
+            MetaVersion::V2 => self
+                .file_tree
+                .iter()
+                .flatten()
+                .map(|file| file.length.div_ceil(self.piece_length as u64) as usize)
+                .sum(),
+            _ => self.pieces.len() / 20,
+        }
+    }
+    /// Length of piece `index`, only the last piece can be shorter than `piece length`,
+    /// or for v2-only torrents the last piece of every file
+    pub fn piece_size(&self, index: u32) -> u32 {
+        if self.version() == MetaVersion::V2 {
+            return self.v2_piece(index).map_or(0, |piece| piece.length);
+        }
+        let start = index as u64 * self.piece_length as u64;
+        let remaining = self.total_length().saturating_sub(start);
+        remaining.min(self.piece_length as u64) as u32
+    }
+    /// The v2 file piece `index` belongs to, v2 pieces never span files and piece indexes
+    /// count through the files of the `file tree` in order
+    pub fn v2_piece(&self, index: u32) -> Option<V2Piece<'_>> {
+        let piece_length = self.piece_length as u64;
+        let mut first = 0;
+        for file in self.file_tree.iter().flatten() {
+            let count = file.length.div_ceil(piece_length);
+            if (index as u64) < first + count {
+                let index = (index as u64 - first) as u32;
+                let start = index as u64 * piece_length;
+                return Some(V2Piece {
+                    file,
+                    index,
+                    length: (file.length - start).min(piece_length) as u32,
+                });
+            }
+            first += count;
+        }
+        None
+    }
+}
+
+impl TorrentFile {
+    pub fn from_value(file: &borrowed::Value) -> Result<TorrentFile, MetainfoError> {
+        let length = required_int(file, "length")?;
+        if length < 0 {
+            return Err(MetainfoError::OutOfRange {
+                field: "length".to_string(),
+                value: length,
+            });
+        }
+        let (attr, symlink_path) = attributes(file)?;
+        Ok(TorrentFile {
+            path: name_list(file, "path")?,
+            length,
+            md5sum: optional_str(file, "md5sum")?,
+            attr,
+            symlink_path,
+            sha1: sha1(file)?,
+        })
+    }
+    /// Whether `attr` holds `flag`: `p` padding, `x` executable, `h` hidden or `l` symlink
+    /// [spec](http://bittorrent.org/beps/bep_0047.html)
+    pub fn has_attr(&self, flag: char) -> bool {
+        self.attr.as_deref().is_some_and(|attr| attr.contains(flag))
+    }
+    /// Padding files only fill the gap up to the next piece boundary and are never written
+    pub fn is_padding(&self) -> bool {
+        self.has_attr('p')
+    }
+}
+
+impl V2File {
+    fn from_value(leaf: &borrowed::Value, path: Vec<String>) -> Result<V2File, MetainfoError> {
+        let length = required_int(leaf, "length")?;
+        let length = u64::try_from(length).map_err(|_| MetainfoError::OutOfRange {
+            field: "length".to_string(),
+            value: length,
+        })?;
+        let pieces_root = match leaf.get("pieces root") {
+            Some(root) => Some(
+                root.as_bytes()
+                    .and_then(|root| Hash::try_from(root).ok())
+                    .ok_or_else(|| wrong_type("pieces root", "a 32-byte hash"))?,
+            ),
+            None if length > 0 => {
+                return Err(MetainfoError::MissingField {
+                    field: "pieces root".to_string(),
+                })
+            }
+            None => None,
+        };
+        let (attr, symlink_path) = attributes(leaf)?;
+        Ok(V2File {
+            path,
+            length,
+            pieces_root,
+            attr,
+            symlink_path,
+        })
+    }
+    /// Whether `attr` holds `flag`, `x` executable, `h` hidden or `l` symlink
+    pub fn has_attr(&self, flag: char) -> bool {
+        self.attr.as_deref().is_some_and(|attr| attr.contains(flag))
+    }
+}
+
+/// BEP 47 `attr` of a file and its `symlink path`, which symlinks must have
+fn attributes(
+    file: &borrowed::Value,
+) -> Result<(Option<String>, Option<Vec<String>>), MetainfoError> {
+    let attr = optional_str(file, "attr")?;
+    let symlink_path = match file.get("symlink path") {
+        Some(path) => Some(string_list(path, "symlink path")?),
+        None if attr.as_deref().is_some_and(|attr| attr.contains('l')) => {
+            return Err(MetainfoError::MissingField {
+                field: "symlink path".to_string(),
+            })
+        }
+        None => None,
+    };
+    Ok((attr, symlink_path))
+}
+
+/// Optional `sha1` of a file's content
+fn sha1(file: &borrowed::Value) -> Result<Option<ByteBuf>, MetainfoError> {
+    file.get("sha1")
+        .map(|hash| {
+            hash.as_bytes()
+                .filter(|hash| hash.len() == 20)
+                .map(ByteBuf::from)
+                .ok_or_else(|| wrong_type("sha1", "a 20-byte hash"))
+        })
+        .transpose()
+}
+
+/// Flattens a `file tree` into its files, in tree order. Directories are dictionaries keyed by
+/// name, files are dictionaries with a single empty key holding the file's details.
+fn parse_file_tree(
+    node: &borrowed::Value,
+    path: &mut Vec<String>,
+    files: &mut Vec<V2File>,
+) -> Result<(), MetainfoError> {
+    let field = std::iter::once("file tree")
+        .chain(path.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(".");
+    let entries = node
+        .as_dict()
+        .ok_or_else(|| wrong_type(&field, "a dictionary"))?;
+    for (key, child) in entries {
+        if key.is_empty() {
+            if path.is_empty() {
+                return Err(wrong_type("file tree", "a directory"));
+            }
+            let file = V2File::from_value(child, path.clone()).map_err(|err| err.within(&field))?;
+            files.push(file);
+            continue;
+        }
+        let name = std::str::from_utf8(key)
+            .map_err(|_| wrong_type(&field, "a dictionary with UTF-8 keys"))?;
+        path.push(name.to_string());
+        parse_file_tree(child, path, files)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+/// `piece layers`, a dictionary from `pieces root` to the concatenated piece hashes
+fn parse_piece_layers(layers: &borrowed::Value) -> Result<BTreeMap<Hash, Vec<u8>>, MetainfoError> {
+    let entries = layers
+        .as_dict()
+        .ok_or_else(|| wrong_type("piece layers", "a dictionary"))?;
+    entries
+        .iter()
+        .map(|(root, layer)| {
+            let root =
+                Hash::try_from(*root).map_err(|_| wrong_type("piece layers", "32-byte keys"))?;
+            let layer = layer
+                .as_bytes()
+                .filter(|layer| layer.len().is_multiple_of(32))
+                .ok_or_else(|| wrong_type("piece layers", "concatenated 32-byte hashes"))?;
+            Ok((root, layer.to_vec()))
+        })
+        .collect()
+}
+
+fn required<'v, 'a>(
+    dict: &'v borrowed::Value<'a>,
+    key: &str,
+) -> Result<&'v borrowed::Value<'a>, MetainfoError> {
+    dict.get(key).ok_or_else(|| MetainfoError::MissingField {
+        field: key.to_string(),
+    })
+}
+
+fn wrong_type(key: &str, expected: &'static str) -> MetainfoError {
+    MetainfoError::WrongType {
+        field: key.to_string(),
+        expected,
+    }
+}
+
+fn as_string(value: &borrowed::Value, key: &str) -> Result<String, MetainfoError> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| wrong_type(key, "a UTF-8 string"))
+}
+
+fn as_list<'v, 'a>(
+    value: &'v borrowed::Value<'a>,
+    key: &str,
+) -> Result<&'v [borrowed::Value<'a>], MetainfoError> {
+    value.as_list().ok_or_else(|| wrong_type(key, "a list"))
+}
+
+/// Fails on a dictionary at any depth that has the same key more than once
+fn unique_keys(value: &borrowed::Value) -> Result<(), MetainfoError> {
+    match duplicate_key(value) {
+        Some(field) => Err(MetainfoError::DuplicateKey { field }),
+        None => Ok(()),
+    }
+}
+
+/// Path to the first repeated dictionary key below `value`, e.g. `files[2].length`
+fn duplicate_key(value: &borrowed::Value) -> Option<String> {
+    let nested = |parent: String, child: String| {
+        if child.starts_with('[') {
+            format!("{parent}{child}")
+        } else {
+            format!("{parent}.{child}")
+        }
+    };
+    match value {
+        borrowed::Value::Dict(dict) => {
+            let mut keys = HashSet::new();
+            for (key, value) in dict {
+                let field = String::from_utf8_lossy(key).into_owned();
+                if !keys.insert(*key) {
+                    return Some(field);
+                }
+                if let Some(child) = duplicate_key(value) {
+                    return Some(nested(field, child));
+                }
+            }
+            None
+        }
+        borrowed::Value::List(list) => list.iter().enumerate().find_map(|(index, value)| {
+            duplicate_key(value).map(|child| nested(format!("[{index}]"), child))
+        }),
+        _ => None,
+    }
+}
+
+fn optional_str(dict: &borrowed::Value, key: &str) -> Result<Option<String>, MetainfoError> {
+    dict.get(key).map(|value| as_string(value, key)).transpose()
+}
+
+fn required_int(dict: &borrowed::Value, key: &str) -> Result<i64, MetainfoError> {
+    required(dict, key)?
+        .as_int()
+        .ok_or_else(|| wrong_type(key, "an integer"))
+}
+
+fn optional_int(dict: &borrowed::Value, key: &str) -> Result<Option<i64>, MetainfoError> {
+    dict.get(key)
+        .map(|value| value.as_int().ok_or_else(|| wrong_type(key, "an integer")))
+        .transpose()
+}
+
+/// A file or directory name, from `<key>.utf-8` when it is valid UTF-8, otherwise from `key`
+/// with bytes that are not UTF-8 replaced, as some clients write names in the system encoding
+fn name_str(dict: &borrowed::Value, key: &str) -> Result<String, MetainfoError> {
+    if let Some(name) = dict
+        .get(&format!("{key}.utf-8"))
+        .and_then(|name| name.as_str())
+    {
+        return Ok(name.to_string());
+    }
+    required(dict, key)?
+        .as_bytes()
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .ok_or_else(|| wrong_type(key, "a byte string"))
+}
+
+/// Path components, from `<key>.utf-8` when it is a list of valid UTF-8 strings, otherwise
+/// from `key` as for `name_str`
+fn name_list(dict: &borrowed::Value, key: &str) -> Result<Vec<String>, MetainfoError> {
+    let utf8 = dict
+        .get(&format!("{key}.utf-8"))
+        .and_then(|path| string_list(path, key).ok());
+    if let Some(path) = utf8 {
+        return Ok(path);
+    }
+    as_list(required(dict, key)?, key)?
+        .iter()
+        .map(|component| {
+            component
+                .as_bytes()
+                .map(|component| String::from_utf8_lossy(component).into_owned())
+                .ok_or_else(|| wrong_type(key, "a list of byte strings"))
+        })
+        .collect()
+}
+
+fn string_list(value: &borrowed::Value, key: &str) -> Result<Vec<String>, MetainfoError> {
+    as_list(value, key)?
+        .iter()
+        .map(|item| as_string(item, key))
+        .collect()
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct TorrentFile {
@@ -65,6 +1010,44 @@ pub struct TorrentFile {
     /// A 32-character hexadecimal string corresponding to the MD5 sum of the file
     #[serde(default)]
     pub md5sum: Option<String>,
+    /// File attributes, `p` marks a padding file, `x` an executable, `h` a hidden file and
+    /// `l` a symlink
+    /// [spec](http://bittorrent.org/beps/bep_0047.html)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attr: Option<String>,
+    /// Target of a symlink, as path components from the root of the torrent
+    #[serde(rename = "symlink path")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symlink_path: Option<Vec<String>>,
+    /// SHA-1 of the file's content
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha1: Option<ByteBuf>,
+}
+
+/// A file of a v2 torrent, from the `file tree`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct V2File {
+    pub path: Vec<String>,
+    pub length: u64,
+    /// Root of the file's merkle tree, absent for empty files
+    pub pieces_root: Option<Hash>,
+    /// BEP 47 attributes, as for v1 files
+    pub attr: Option<String>,
+    /// As [`TorrentFile::symlink_path`]
+    pub symlink_path: Option<Vec<String>>,
+}
+
+/// A piece of a v2 torrent and the file holding it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct V2Piece<'f> {
+    pub file: &'f V2File,
+    /// Index of the piece within the file
+    pub index: u32,
+    /// Bytes of the file in the piece, less than `piece length` for the file's last piece
+    pub length: u32,
 }
 
 #[allow(dead_code)]
@@ -77,13 +1060,25 @@ pub struct Info {
     pub piece_length: u32,
     /// Concatenation of all 20-byte SHA1 hash values, one per piece (common to both mode)
     pub pieces: ByteBuf,
-    // #[serde(default)]
-    // pub private: Option<u8>,
+    /// 1 when peers may only come from the torrent's trackers
+    /// [spec](http://bittorrent.org/beps/bep_0027.html)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private: Option<u8>,
+    /// Tag of the tracker or site the torrent was made for, it only serves to change the info hash
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
     /// Single-file:
     /// A 32-character hexadecimal string corresponding to the MD5 sum of the file
     #[serde(default)]
     pub md5sum: Option<String>,
     /// Single-file:
+    /// SHA-1 of the file's content
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha1: Option<ByteBuf>,
+    /// Single-file:
     /// Length of the file in bytes
     #[serde(default)]
     pub length: Option<usize>,
@@ -93,7 +1088,192 @@ pub struct Info {
     pub files: Option<Vec<TorrentFile>>,
     // #[serde(default)]
     // pub path: Option<Vec<String>>,
-    // #[serde(default)]
-    // #[serde(rename = "root hash")]
-    // pub root_hash: Option<String>,
+    /// v2: `meta version`, 2 for v2 and hybrid torrents
+    #[serde(skip)]
+    pub meta_version: Option<i64>,
+    /// v2: the files of the `file tree`, in tree order
+    #[serde(skip)]
+    pub file_tree: Option<Vec<V2File>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIECE_LENGTH: i64 = 16384;
+
+    /// A single-file `info` of `length` bytes with `pieces` bytes of piece hashes
+    fn single_file(length: i64, pieces: usize) -> Vec<(&'static str, Value)> {
+        vec![
+            ("name", Value::from("a")),
+            ("piece length", Value::from(PIECE_LENGTH)),
+            ("pieces", Value::from(vec![0; pieces])),
+            ("length", Value::from(length)),
+        ]
+    }
+
+    /// A `files` entry, `path` split on `/`
+    fn file(path: &str, length: i64) -> Value {
+        [
+            ("length", Value::from(length)),
+            (
+                "path",
+                path.split('/').map(Value::from).collect::<Vec<_>>().into(),
+            ),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    /// Loads a torrent whose `info` has `info`, later entries replacing earlier ones
+    fn load(info: Vec<(&str, Value)>) -> Result<Torrent, MetainfoError> {
+        load_with(vec![], info)
+    }
+
+    fn load_with(
+        metainfo: Vec<(&str, Value)>,
+        info: Vec<(&str, Value)>,
+    ) -> Result<Torrent, MetainfoError> {
+        Torrent::from_bytes(&encode(metainfo, info))
+    }
+
+    fn encode(metainfo: Vec<(&str, Value)>, info: Vec<(&str, Value)>) -> Vec<u8> {
+        let info: Value = info.into_iter().collect();
+        let metainfo: Value = metainfo.into_iter().chain([("info", info)]).collect();
+        metainfo.encode()
+    }
+
+    fn without(mut info: Vec<(&'static str, Value)>, key: &str) -> Vec<(&'static str, Value)> {
+        info.retain(|(entry, _)| *entry != key);
+        info
+    }
+
+    #[test]
+    fn loads_a_valid_torrent() {
+        let torrent = load(single_file(20000, 40)).unwrap();
+        assert_eq!(torrent.info.piece_count(), 2);
+        assert_eq!(torrent.info.total_length(), 20000);
+        assert_eq!(torrent.tracker(), None);
+    }
+
+    #[test]
+    fn rejects_duplicate_keys_at_any_depth() {
+        let mut info = without(single_file(20000, 40), "length");
+        info.push(("files", vec![file("b", 1), file("c", 19999)].into()));
+        let bytes = encode(vec![], info);
+        // repeat the `length` of the second file, which a last-wins reader would take
+        let duplicated = String::from_utf8_lossy(&bytes).replace(
+            "d6:lengthi19999e4:pathl1:cee",
+            "d6:lengthi19999e6:lengthi1e4:pathl1:cee",
+        );
+        let err = Torrent::from_bytes(duplicated.as_bytes()).unwrap_err();
+        assert!(
+            matches!(&err, MetainfoError::DuplicateKey { field } if field == "info.files[1].length"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_bencode() {
+        let err = Torrent::from_bytes(b"d4:infod").unwrap_err();
+        assert!(matches!(err, MetainfoError::Decode(_)), "{err}");
+    }
+
+    #[test]
+    fn rejects_missing_fields() {
+        let err = load(without(single_file(20000, 40), "name")).unwrap_err();
+        assert!(
+            matches!(&err, MetainfoError::MissingField { field } if field == "info.name"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_types() {
+        let mut info = single_file(20000, 40);
+        info.push(("name", Value::from(1)));
+        let err = load(info).unwrap_err();
+        assert!(
+            matches!(&err, MetainfoError::WrongType { field, .. } if field == "info.name"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn rejects_negative_creation_date() {
+        let err = load_with(
+            vec![("creation date", Value::from(-1))],
+            single_file(20000, 40),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(&err, MetainfoError::OutOfRange { field, value: -1 } if field == "creation date"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_piece_length() {
+        let mut info = single_file(20000, 40);
+        info.push(("piece length", Value::from(0)));
+        let err = load(info).unwrap_err();
+        assert!(matches!(err, MetainfoError::InvalidPieceLength(0)), "{err}");
+    }
+
+    #[test]
+    fn rejects_pieces_not_multiple_of_20() {
+        let err = load(single_file(20000, 30)).unwrap_err();
+        assert!(
+            matches!(err, MetainfoError::PiecesNotMultipleOf20 { length: 30 }),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn rejects_piece_count_mismatch() {
+        let err = load(single_file(20000, 20)).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                MetainfoError::PieceCountMismatch {
+                    expected: 2,
+                    actual: 1
+                }
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn rejects_both_length_and_files() {
+        let mut info = single_file(20000, 40);
+        info.push(("files", vec![file("b", 20000)].into()));
+        let err = load(info).unwrap_err();
+        assert!(matches!(err, MetainfoError::BothLengthAndFiles), "{err}");
+    }
+
+    #[test]
+    fn rejects_no_length_or_files() {
+        let err = load(without(single_file(20000, 40), "length")).unwrap_err();
+        assert!(matches!(err, MetainfoError::NoLengthOrFiles), "{err}");
+    }
+
+    #[test]
+    fn rejects_empty_file_list() {
+        let mut info = without(single_file(20000, 40), "length");
+        info.push(("files", Value::List(vec![])));
+        let err = load(info).unwrap_err();
+        assert!(matches!(err, MetainfoError::EmptyFileList), "{err}");
+    }
+
+    #[test]
+    fn rejects_overflowing_lengths() {
+        let mut info = without(single_file(20000, 40), "length");
+        info.push(("files", vec![file("b", i64::MAX); 3].into()));
+        let err = load(info).unwrap_err();
+        assert!(matches!(err, MetainfoError::TooLong), "{err}");
+        // fits in 64 bits, but not in 32-bit piece indexes
+        let err = load(single_file(i64::MAX, 40)).unwrap_err();
+        assert!(matches!(err, MetainfoError::TooLong), "{err}");
+    }
 }
@@ -3,11 +3,14 @@ use std::{
     net::{Ipv4Addr, SocketAddrV4},
 };
 
+use anyhow::{bail, ensure, Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 use urlencoding::encode_binary;
 
-use crate::torrent::{self, Torrent};
+use crate::bencode_parser::{
+    borrowed::Value, decode_borrowed_with_limits, DecodeLimits, StreamDecoder,
+};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TrackerRequest {
@@ -21,12 +24,13 @@ pub struct TrackerRequest {
 }
 
 impl TrackerRequest {
-    pub fn new(torrent: &Torrent, info_hash: [u8; 20]) -> TrackerRequest {
+    /// `left` is the number of bytes still to download, the torrent's total length at the start
+    pub fn new(info_hash: [u8; 20], left: u64) -> TrackerRequest {
         let peer_id = "00112233445566778899";
         let port = 6881;
         let uploaded = 0;
         let downloaded = 0;
-        let left = torrent.info.length.unwrap();
+        let left = left as usize;
         let compact = 1;
 
         TrackerRequest {
@@ -43,7 +47,11 @@ impl TrackerRequest {
         let info_hash_url_encoded = encode_binary(&info_hash).into_owned();
         info_hash_url_encoded
     }
-    pub async fn request(&self, info_hash_url: String, tracker_url: &String) -> TrackerResponse {
+    pub async fn request(
+        &self,
+        info_hash_url: String,
+        tracker_url: &String,
+    ) -> Result<TrackerResponse> {
         let mut params = HashMap::new();
 
         // Define other parameters
@@ -67,9 +75,22 @@ impl TrackerRequest {
 
         println!("request: {}", f.clone());
 
-        let body = reqwest::get(f).await.unwrap().bytes().await.unwrap();
-        println!("body: {:?}", body);
-        serde_bencode::from_bytes::<TrackerResponse>(&body).unwrap()
+        // decode the body as it arrives instead of buffering the whole response first
+        let mut response = reqwest::get(f).await?;
+        let limits = DecodeLimits::network();
+        let mut decoder = StreamDecoder::with_limits(limits);
+        while let Some(chunk) = response.chunk().await? {
+            decoder.feed(&chunk);
+            if let Some(body) = decoder
+                .next_value_bytes()
+                .context("invalid tracker response")?
+            {
+                let (body, _) = decode_borrowed_with_limits(&body, limits)
+                    .context("invalid tracker response")?;
+                return TrackerResponse::from_value(&body);
+            }
+        }
+        bail!("tracker response ended before a complete bencode value");
     }
 }
 
@@ -80,9 +101,35 @@ pub struct TrackerResponse {
 }
 
 impl TrackerResponse {
+    pub fn from_value(body: &Value) -> Result<TrackerResponse> {
+        if let Some(reason) = body.get("failure reason") {
+            bail!(
+                "tracker returned failure: {}",
+                String::from_utf8_lossy(reason.as_bytes().unwrap_or_default())
+            );
+        }
+        let interval = body
+            .get("interval")
+            .and_then(Value::as_int)
+            .context("tracker response has no interval")? as u64;
+        let peers = body
+            .get("peers")
+            .and_then(Value::as_bytes)
+            .context("tracker response has no compact peers")?;
+        ensure!(
+            peers.len().is_multiple_of(6),
+            "tracker response has {} bytes of compact peers, not a multiple of 6",
+            peers.len()
+        );
+        Ok(TrackerResponse {
+            interval,
+            peers: ByteBuf::from(peers),
+        })
+    }
+    /// Compact peers, 4 bytes of IPv4 address and 2 of port each
     pub fn get_peers(&self) -> Vec<SocketAddrV4> {
         self.peers
-            .chunks(6)
+            .chunks_exact(6)
             .map(|chunk| {
                 let ip = Ipv4Addr::new(chunk[0], chunk[1], chunk[2], chunk[3]);
                 let port = u16::from_be_bytes([chunk[4], chunk[5]]);
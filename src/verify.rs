@@ -0,0 +1,414 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, ErrorKind, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use md5::Md5;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    create::parallel_map,
+    storage::Storage,
+    torrent::{MetaVersion, Torrent},
+};
+
+/// Bytes read at a time when hashing whole files
+const CHUNK_LENGTH: usize = 1024 * 1024;
+
+/// State of a piece of data on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PieceStatus {
+    Valid,
+    /// A file holding part of the piece does not exist or is too short
+    Missing,
+    /// The data does not match the piece's hash
+    Corrupt,
+}
+
+/// How much of a file on disk is verified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStatus {
+    pub path: PathBuf,
+    pub length: u64,
+    /// Pieces holding part of the file
+    pub pieces: u32,
+    pub valid_pieces: u32,
+    /// Whether the file exists, relevant for empty files that no piece covers
+    pub exists: bool,
+}
+
+impl FileStatus {
+    pub fn is_complete(&self) -> bool {
+        self.exists && self.valid_pieces == self.pieces
+    }
+}
+
+/// Result of checking a torrent's data on disk against its piece hashes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Status of every piece, by index
+    pub pieces: Vec<PieceStatus>,
+    /// Every file other than padding and symlinks, in torrent order
+    pub files: Vec<FileStatus>,
+}
+
+impl VerifyReport {
+    /// Indexes of the pieces that are missing or corrupt
+    pub fn failed_pieces(&self) -> Vec<u32> {
+        self.pieces_with(|status| status != PieceStatus::Valid)
+    }
+    pub fn is_complete(&self) -> bool {
+        self.failed_pieces().is_empty() && self.files.iter().all(FileStatus::is_complete)
+    }
+    fn pieces_with(&self, filter: impl Fn(PieceStatus) -> bool) -> Vec<u32> {
+        (0..self.pieces.len() as u32)
+            .filter(|&index| filter(self.pieces[index as usize]))
+            .collect()
+    }
+}
+
+/// Hashes the data of `torrent` laid out under `output`, as `Peer::download_torrent` writes it,
+/// using every core
+pub fn verify(torrent: &Torrent, output: &Path) -> Result<VerifyReport> {
+    let storage = Storage::new(&torrent.info, output);
+    let piece_length = torrent.info.piece_length as u64;
+    let pieces = parallel_map(torrent.info.piece_count(), |index| {
+        let index = index as u32;
+        let length = torrent.info.piece_size(index) as u64;
+        match storage.read_range(index as u64 * piece_length, length) {
+            Ok(data) if torrent.verify_piece(index, &data) => Ok(PieceStatus::Valid),
+            Ok(_) => Ok(PieceStatus::Corrupt),
+            Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::UnexpectedEof) => {
+                Ok(PieceStatus::Missing)
+            }
+            Err(err) => Err(err).with_context(|| format!("failed to read piece {index}")),
+        }
+    })?;
+    let files = storage
+        .files()
+        .iter()
+        .filter(|file| file.holds_data())
+        .map(|file| {
+            let covering = if file.length == 0 {
+                0..0
+            } else {
+                file.offset / piece_length..(file.offset + file.length).div_ceil(piece_length)
+            };
+            FileStatus {
+                path: file.path.clone(),
+                length: file.length,
+                pieces: covering.clone().count() as u32,
+                valid_pieces: covering
+                    .filter(|&index| pieces[index as usize] == PieceStatus::Valid)
+                    .count() as u32,
+                exists: file.path.is_file(),
+            }
+        })
+        .collect();
+    Ok(VerifyReport { pieces, files })
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let missing = self.pieces_with(|status| status == PieceStatus::Missing);
+        let corrupt = self.pieces_with(|status| status == PieceStatus::Corrupt);
+        if !missing.is_empty() {
+            writeln!(f, "Missing Pieces: {}", ranges(&missing))?;
+        }
+        if !corrupt.is_empty() {
+            writeln!(f, "Corrupt Pieces: {}", ranges(&corrupt))?;
+        }
+        writeln!(f, "Files:")?;
+        for file in &self.files {
+            let status = if file.is_complete() {
+                "complete".to_string()
+            } else if !file.exists {
+                "missing".to_string()
+            } else {
+                format!("{}/{} pieces", file.valid_pieces, file.pieces)
+            };
+            writeln!(f, "  {status:<16}{}", file.path.display())?;
+        }
+        writeln!(
+            f,
+            "Pieces: {} of {} valid, {} missing, {} corrupt",
+            self.pieces.len() - missing.len() - corrupt.len(),
+            self.pieces.len(),
+            missing.len(),
+            corrupt.len()
+        )
+    }
+}
+
+/// Sorted indexes with runs collapsed, e.g. `0-3, 7`
+fn ranges(indexes: &[u32]) -> String {
+    let mut runs: Vec<(u32, u32)> = vec![];
+    for &index in indexes {
+        match runs.last_mut() {
+            Some((_, last)) if *last + 1 == index => *last = index,
+            _ => runs.push((index, index)),
+        }
+    }
+    runs.iter()
+        .map(|&(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{first}-{last}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// MD5 and SHA-1 of a file's content, as `md5sum` and `sha1` hold them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksums {
+    pub md5: [u8; 16],
+    pub sha1: [u8; 20],
+}
+
+/// Hashes the file at `path` with both algorithms in one pass
+pub fn file_checksums(path: &Path) -> io::Result<Checksums> {
+    let mut file = File::open(path)?;
+    let mut md5 = Md5::new();
+    let mut sha1 = Sha1::new();
+    let mut chunk = vec![0; CHUNK_LENGTH];
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        md5.update(&chunk[..read]);
+        sha1.update(&chunk[..read]);
+    }
+    Ok(Checksums {
+        md5: md5.finalize().into(),
+        sha1: sha1.finalize().into(),
+    })
+}
+
+/// A file compared with one of the checksums the torrent gives for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumCheck {
+    pub path: PathBuf,
+    /// `md5` or `sha1`
+    pub algorithm: &'static str,
+    /// Hex checksum from the torrent
+    pub expected: String,
+    /// Hex checksum of the file, `None` when it could not be read
+    pub actual: Option<String>,
+}
+
+impl ChecksumCheck {
+    pub fn matches(&self) -> bool {
+        self.actual
+            .as_deref()
+            .is_some_and(|actual| actual.eq_ignore_ascii_case(&self.expected))
+    }
+}
+
+impl fmt::Display for ChecksumCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path.display();
+        match &self.actual {
+            _ if self.matches() => write!(f, "{} ok: {path}", self.algorithm),
+            Some(actual) => write!(
+                f,
+                "{} mismatch: {path}, expected {}, got {actual}",
+                self.algorithm, self.expected
+            ),
+            None => write!(f, "{} unreadable: {path}", self.algorithm),
+        }
+    }
+}
+
+/// Compares the files of `torrent` under `output` with their `md5sum` and `sha1`, for the files
+/// that have them, using every core. These are only found next to v1 file entries, so there is
+/// nothing to check for v2-only torrents.
+pub fn check_checksums(torrent: &Torrent, output: &Path) -> Result<Vec<ChecksumCheck>> {
+    let info = &torrent.info;
+    if info.version() == MetaVersion::V2 {
+        return Ok(vec![]);
+    }
+    let storage = Storage::new(info, output);
+    // v1 layouts have one span per entry of `files`
+    let expected: Vec<_> = match &info.files {
+        Some(files) => files
+            .iter()
+            .zip(storage.files())
+            .filter(|(file, _)| !file.is_padding())
+            .map(|(file, span)| (span.path.clone(), file.md5sum.clone(), file.sha1.clone()))
+            .collect(),
+        None => vec![(
+            storage.files()[0].path.clone(),
+            info.md5sum.clone(),
+            info.sha1.clone(),
+        )],
+    };
+    let expected: Vec<_> = expected
+        .into_iter()
+        .filter(|(_, md5, sha1)| md5.is_some() || sha1.is_some())
+        .collect();
+    let actual = parallel_map(expected.len(), |index| {
+        Ok(file_checksums(&expected[index].0).ok())
+    })?;
+    let mut checks = vec![];
+    for ((path, md5, sha1), actual) in expected.into_iter().zip(actual) {
+        if let Some(md5) = md5 {
+            checks.push(ChecksumCheck {
+                path: path.clone(),
+                algorithm: "md5",
+                expected: md5,
+                actual: actual.map(|actual| hex::encode(actual.md5)),
+            });
+        }
+        if let Some(sha1) = sha1 {
+            checks.push(ChecksumCheck {
+                path,
+                algorithm: "sha1",
+                expected: hex::encode(sha1),
+                actual: actual.map(|actual| hex::encode(actual.sha1)),
+            });
+        }
+    }
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create::TorrentBuilder, test_util::TempDir, DEFAULT_BLOCK_LENGTH};
+
+    /// `content/a.bin` and `content/b.bin` of 20000 and 30000 bytes, in 4 pieces, with the
+    /// torrent made from them
+    fn content(name: &str) -> (TempDir, Torrent) {
+        let dir = TempDir::new(name);
+        dir.write("content/a.bin", 20000);
+        dir.write("content/b.bin", 30000);
+        let bytes = TorrentBuilder::new(dir.0.join("content"))
+            .piece_length(DEFAULT_BLOCK_LENGTH)
+            .build()
+            .unwrap();
+        (dir, Torrent::from_bytes(&bytes).unwrap())
+    }
+
+    #[test]
+    fn collapses_runs() {
+        assert_eq!(ranges(&[]), "");
+        assert_eq!(ranges(&[5]), "5");
+        assert_eq!(ranges(&[1, 3]), "1, 3");
+        assert_eq!(ranges(&[0, 1, 2, 3, 7, 9, 10]), "0-3, 7, 9-10");
+    }
+
+    #[test]
+    fn finds_complete_data_valid() {
+        let (dir, torrent) = content("verify-complete");
+        let report = verify(&torrent, &dir.0).unwrap();
+        assert_eq!(report.pieces, [PieceStatus::Valid; 4]);
+        assert!(report.is_complete());
+        assert!(report
+            .to_string()
+            .ends_with("Pieces: 4 of 4 valid, 0 missing, 0 corrupt\n"));
+    }
+
+    #[test]
+    fn reports_corrupt_pieces() {
+        let (dir, torrent) = content("verify-corrupt");
+        // the first byte of `b.bin` is in piece 1, which `a.bin` shares
+        std::fs::write(dir.0.join("content/b.bin"), vec![0; 30000]).unwrap();
+        let report = verify(&torrent, &dir.0).unwrap();
+        use PieceStatus::*;
+        assert_eq!(report.pieces, [Valid, Corrupt, Corrupt, Corrupt]);
+        assert_eq!(report.failed_pieces(), [1, 2, 3]);
+        let valid: Vec<_> = report
+            .files
+            .iter()
+            .map(|file| (file.valid_pieces, file.pieces))
+            .collect();
+        assert_eq!(valid, [(1, 2), (0, 3)]);
+        assert!(!report.is_complete());
+        assert!(report.to_string().starts_with("Corrupt Pieces: 1-3\n"));
+    }
+
+    #[test]
+    fn reports_missing_files() {
+        let (dir, torrent) = content("verify-missing");
+        std::fs::remove_file(dir.0.join("content/a.bin")).unwrap();
+        let report = verify(&torrent, &dir.0).unwrap();
+        use PieceStatus::*;
+        assert_eq!(report.pieces, [Missing, Missing, Valid, Valid]);
+        assert!(!report.files[0].exists);
+        assert!(report.files[1].exists);
+        let text = report.to_string();
+        assert!(text.starts_with("Missing Pieces: 0-1\n"), "{text}");
+        assert!(text.contains("  missing"), "{text}");
+        assert!(text.contains("  2/3 pieces"), "{text}");
+    }
+
+    #[test]
+    fn hashes_files_with_md5_and_sha1() {
+        let dir = TempDir::new("verify-digest");
+        let path = dir.0.join("abc");
+        std::fs::write(&path, "abc").unwrap();
+        let checksums = file_checksums(&path).unwrap();
+        assert_eq!(
+            hex::encode(checksums.md5),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+        assert_eq!(
+            hex::encode(checksums.sha1),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+    }
+
+    #[test]
+    fn checks_file_checksums() {
+        let dir = TempDir::new("verify-checksums");
+        dir.write("content/a.bin", 20000);
+        dir.write("content/b.bin", 30000);
+        dir.write("content/c.bin", 10);
+        let bytes = TorrentBuilder::new(dir.0.join("content"))
+            .checksums(true)
+            .build()
+            .unwrap();
+        let torrent = Torrent::from_bytes(&bytes).unwrap();
+        let status = |checks: Vec<ChecksumCheck>| -> Vec<_> {
+            checks
+                .iter()
+                .map(|check| (check.algorithm, check.matches(), check.actual.is_some()))
+                .collect()
+        };
+        let checks = check_checksums(&torrent, &dir.0).unwrap();
+        assert_eq!(checks.len(), 6);
+        assert!(checks.iter().all(ChecksumCheck::matches));
+        std::fs::write(dir.0.join("content/b.bin"), "changed").unwrap();
+        std::fs::remove_file(dir.0.join("content/c.bin")).unwrap();
+        assert_eq!(
+            status(check_checksums(&torrent, &dir.0).unwrap()),
+            [
+                ("md5", true, true),
+                ("sha1", true, true),
+                ("md5", false, true),
+                ("sha1", false, true),
+                ("md5", false, false),
+                ("sha1", false, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn has_no_checksums_to_check_for_v2() {
+        let dir = TempDir::new("verify-v2");
+        let file = dir.write("a.bin", 100);
+        let bytes = TorrentBuilder::new(&file)
+            .meta_version(MetaVersion::V2)
+            .build()
+            .unwrap();
+        let torrent = Torrent::from_bytes(&bytes).unwrap();
+        assert_eq!(check_checksums(&torrent, &file).unwrap(), []);
+    }
+}
@@ -0,0 +1,200 @@
+use std::{collections::VecDeque, fmt, path::Path, sync::Arc};
+
+use anyhow::{bail, ensure, Context, Result};
+use reqwest::{header::RANGE, Client, StatusCode};
+use tokio::sync::Mutex;
+use urlencoding::{encode, encode_binary};
+
+use crate::{storage::Storage, torrent::Torrent};
+
+/// An HTTP server pieces can be downloaded from alongside the peers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebSeed {
+    /// From `url-list`, serves the files under their paths and answers range requests
+    /// [spec](http://bittorrent.org/beps/bep_0019.html)
+    UrlList(String),
+    /// From `httpseeds`, answers `?info_hash=...&piece=...` with the whole piece
+    /// [spec](http://bittorrent.org/beps/bep_0017.html)
+    HttpSeed(String),
+}
+
+impl WebSeed {
+    pub fn from_torrent(torrent: &Torrent) -> Vec<WebSeed> {
+        let url_list = torrent.url_list.iter().cloned().map(WebSeed::UrlList);
+        let httpseeds = torrent.httpseeds.iter().cloned().map(WebSeed::HttpSeed);
+        url_list.chain(httpseeds).collect()
+    }
+    /// Downloads piece `piece_index` and checks it against the torrent's hashes
+    pub async fn download_piece(
+        &self,
+        client: &Client,
+        torrent: &Torrent,
+        piece_index: u32,
+    ) -> Result<Vec<u8>> {
+        let piece_length = torrent.info.piece_size(piece_index);
+        let data = match self {
+            WebSeed::UrlList(url) => {
+                Self::download_ranges(client, url, torrent, piece_index, piece_length).await?
+            }
+            WebSeed::HttpSeed(url) => {
+                let separator = if url.contains('?') { '&' } else { '?' };
+                let url = format!(
+                    "{url}{separator}info_hash={}&piece={piece_index}",
+                    encode_binary(&torrent.swarm_hash())
+                );
+                let response = client.get(&url).send().await?;
+                if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+                    let retry = response.text().await.unwrap_or_default();
+                    bail!("{url} is busy, retry in {} seconds", retry.trim());
+                }
+                let response = response.error_for_status()?;
+                response.bytes().await?.to_vec()
+            }
+        };
+        ensure!(
+            data.len() == piece_length as usize,
+            "piece {piece_index} is {} bytes, expected {piece_length}",
+            data.len()
+        );
+        ensure!(
+            torrent.verify_piece(piece_index, &data),
+            "piece {piece_index} failed its hash check"
+        );
+        Ok(data)
+    }
+    /// A piece from a `url-list` seed, one range request per file the piece covers
+    async fn download_ranges(
+        client: &Client,
+        url: &str,
+        torrent: &Torrent,
+        piece_index: u32,
+        piece_length: u32,
+    ) -> Result<Vec<u8>> {
+        // the spans' paths are the paths on the server
+        let layout = Storage::unsanitized(&torrent.info);
+        let offset = piece_index as u64 * torrent.info.piece_length as u64;
+        let mut data = vec![0; piece_length as usize];
+        for slice in layout.slices(offset, piece_length as u64) {
+            if !slice.file.holds_data() {
+                continue;
+            }
+            let file_url = Self::file_url(url, &torrent.info.name, &slice.file.path);
+            let first = slice.file_offset;
+            let last = first + slice.length - 1;
+            let response = client
+                .get(&file_url)
+                .header(RANGE, format!("bytes={first}-{last}"))
+                .send()
+                .await?
+                .error_for_status()
+                .with_context(|| format!("failed to fetch {file_url}"))?;
+            let partial = response.status() == StatusCode::PARTIAL_CONTENT;
+            let body = response.bytes().await?;
+            // a server ignoring the range sends the whole file
+            let body = if partial {
+                &body[..]
+            } else {
+                body.get(first as usize..=last as usize)
+                    .with_context(|| format!("{file_url} is shorter than expected"))?
+            };
+            ensure!(
+                body.len() == slice.length as usize,
+                "{file_url} sent {} bytes for a {} byte range",
+                body.len(),
+                slice.length
+            );
+            let start = slice.range_offset as usize;
+            data[start..start + body.len()].copy_from_slice(body);
+        }
+        Ok(data)
+    }
+    /// URL of a file of the torrent: a single file is the URL itself unless it names a
+    /// directory, multi-file torrents live under `<url>/<name>/<path>`
+    fn file_url(url: &str, name: &str, path: &Path) -> String {
+        if path.as_os_str().is_empty() {
+            return if url.ends_with('/') {
+                format!("{url}{}", encode(name))
+            } else {
+                url.to_string()
+            };
+        }
+        let mut file_url = url.trim_end_matches('/').to_string();
+        for component in path.iter() {
+            file_url.push('/');
+            file_url.push_str(&encode(&component.to_string_lossy()));
+        }
+        file_url
+    }
+    /// Downloads pieces from the queue and writes them until it runs dry, a piece that fails
+    /// to download or to be written is put back for the peers and ends this seed's share
+    pub async fn run(
+        self,
+        torrent: Arc<Torrent>,
+        storage: Arc<Storage>,
+        queue: Arc<Mutex<VecDeque<u32>>>,
+    ) {
+        let client = Client::new();
+        loop {
+            let Some(piece_index) = queue.lock().await.pop_front() else {
+                return;
+            };
+            let downloaded = async {
+                let data = self.download_piece(&client, &torrent, piece_index).await?;
+                storage
+                    .write_piece(piece_index, &data)
+                    .await
+                    .with_context(|| format!("failed to write piece {piece_index}"))
+            };
+            if let Err(err) = downloaded.await {
+                eprintln!("web seed {self}: {err:#}");
+                queue.lock().await.push_back(piece_index);
+                return;
+            }
+        }
+    }
+}
+
+impl fmt::Display for WebSeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSeed::UrlList(url) | WebSeed::HttpSeed(url) => f.write_str(url),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_file_url_is_the_seed_unless_a_directory() {
+        let url = |seed| WebSeed::file_url(seed, "a b.iso", Path::new(""));
+        assert_eq!(url("http://s/files/a.iso"), "http://s/files/a.iso");
+        assert_eq!(url("http://s/files/"), "http://s/files/a%20b.iso");
+    }
+
+    #[test]
+    fn multi_file_urls_are_below_the_seed() {
+        let path = Path::new("name/sub dir/f#1.txt");
+        let expected = "http://s/files/name/sub%20dir/f%231.txt";
+        assert_eq!(WebSeed::file_url("http://s/files", "name", path), expected);
+        assert_eq!(WebSeed::file_url("http://s/files/", "name", path), expected);
+    }
+
+    #[test]
+    fn url_list_seeds_come_before_httpseeds() {
+        let mut torrent = Torrent::from_bytes(
+            b"d4:infod6:lengthi1e4:name1:a12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaaee",
+        )
+        .unwrap();
+        torrent.url_list = vec!["http://u/".to_string()];
+        torrent.httpseeds = vec!["http://h/seed".to_string()];
+        assert_eq!(
+            WebSeed::from_torrent(&torrent),
+            [
+                WebSeed::UrlList("http://u/".to_string()),
+                WebSeed::HttpSeed("http://h/seed".to_string())
+            ]
+        );
+    }
+}